@@ -1,16 +1,25 @@
-
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
 fn main() {
-    if env::var_os("CARGO_FEATURE_RT").is_some() {
+    if env::var_os("CARGO_FEATURE_BOARD").is_some() {
         let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
         File::create(out.join("memory.x"))
             .unwrap()
             .write_all(include_bytes!("memory.x"))
             .unwrap();
-        // TODO move bytes of pokegame into wherever
+        // The firmware has no filesystem to load a ROM from, so it gets baked in. Without one it
+        // boots into an empty cartridge, which is still enough to hear the APU idle
+        let rom = match env::var_os("GBC_ROM").map(PathBuf::from) {
+            Some(path) => {
+                println!("cargo:rerun-if-changed={}", path.display());
+                fs::read(&path).unwrap()
+            }
+            None => Vec::new(),
+        };
+        fs::write(out.join("rom.gb"), rom).unwrap();
+        println!("cargo:rerun-if-env-changed=GBC_ROM");
         println!("cargo:rustc-link-search={}", out.display());
         println!("cargo:rerun-if-changed=memory.x");
     }
@@ -0,0 +1,53 @@
+//! Runs a ROM headless with its link port on a terminal, such as the board's USB serial port or
+//! a TCP-to-serial shim, so the two can trade over the cable.
+//!
+//! cargo run --example link --no-default-features --features std \
+//!     --target x86_64-unknown-linux-gnu -- game.gbc /dev/ttyACM0
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use gbc_m4::cpu::CPU;
+use gbc_m4::serial::pty::PtyStream;
+use gbc_m4::serial::FramedLink;
+
+/// T-cycles in a frame, which is also how often we catch up with the wall clock
+const FRAME_CYCLES: u32 = 70224;
+const FRAME: Duration = Duration::from_nanos(16_742_706);
+/// Reads a master spends waiting on the other end to advertise before taking 0xFF
+const TIMEOUT: u32 = 100_000;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <rom> <tty>", args[0]);
+        std::process::exit(2);
+    }
+
+    let rom = std::fs::read(&args[1]).expect("couldn't read the ROM");
+    let stream = PtyStream::open(Path::new(&args[2])).expect("couldn't open the tty");
+    let mut link = FramedLink::new(stream, TIMEOUT);
+
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(Box::leak(rom.into_boxed_slice()));
+
+    let mut next = Instant::now();
+    loop {
+        let mut ran = 0;
+        while ran < FRAME_CYCLES {
+            match cpu.execute() {
+                Ok(cycles) => ran += cycles,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            cpu.service_link(&mut link);
+        }
+
+        next += FRAME;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
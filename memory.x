@@ -1,7 +1,8 @@
 MEMORY
 {
   /* Leave 16k for the default bootloader on the ItsyBitsy M4 */
-  FLASH (rx) : ORIGIN = 0x00000000 + 16K, LENGTH = 512K - 16K
+  /* and the last 128k for the stored printouts plus 8k for the settings, see io/flash.rs */
+  FLASH (rx) : ORIGIN = 0x00000000 + 16K, LENGTH = 512K - 16K - 128K - 8K
   RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 192K
 }
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
@@ -0,0 +1,126 @@
+// Per-channel captures, for seeing what each channel is actually doing
+
+/// Takes each channel's level, one sample per output sample. Levels are scaled like the mix,
+/// before panning, master volume and muting.
+pub trait CaptureSink {
+    /// `samples` more for `channel`, 0-3.
+    fn capture(&mut self, channel: usize, samples: &[i16]);
+}
+
+/// Trace colours in BGR555, like the frame: red, green, blue and yellow.
+pub const SCOPE_COLORS: [u16; 4] = [0x001F, 0x03E0, 0x7C00, 0x03FF];
+
+/// Keeps the last `N` samples of every channel and draws them as a four trace oscilloscope,
+/// one lane per channel from the top.
+///
+/// Each trace starts at the channel's first rising edge in the older half of what's kept, so
+/// a steady tone stands still instead of crawling across the screen.
+pub struct Scope<const N: usize> {
+    samples: [[i16; N]; 4],
+    /// Where each channel's next sample goes
+    heads: [usize; 4],
+}
+
+impl<const N: usize> Scope<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [[0; N]; 4],
+            heads: [0; 4],
+        }
+    }
+
+    /// `channel`'s `i`th sample, oldest first.
+    #[inline]
+    fn sample(&self, channel: usize, i: usize) -> i16 {
+        self.samples[channel][(self.heads[channel] + i) % N]
+    }
+
+    /// Where the trace for `channel` should start.
+    fn trigger(&self, channel: usize) -> usize {
+        (1..N / 2)
+            .find(|i| self.sample(channel, i - 1) < 0 && self.sample(channel, *i) >= 0)
+            .unwrap_or(0)
+    }
+
+    /// Draws the traces over `frame`, which is `width` pixels wide, one sample per column.
+    /// Whatever's underneath shows through between them.
+    pub fn render(&self, frame: &mut [u16], width: usize) {
+        let height = frame.len() / width;
+        let lane = height / 4;
+        if lane < 2 {
+            return;
+        }
+
+        for (channel, color) in SCOPE_COLORS.iter().enumerate() {
+            let start = self.trigger(channel);
+            let top = channel * lane;
+            // Full scale is the i16 range, which just about fills the lane
+            let y = |sample: i16| top + (lane - 1) * (32767 - sample as i32) as usize / 65535;
+
+            let mut last = y(self.sample(channel, start));
+            for x in 0..width.min(N - start) {
+                let now = y(self.sample(channel, start + x));
+                // Join up with the last column so edges are solid
+                let (from, to) = if now < last { (now, last) } else { (last, now) };
+                for row in from..=to {
+                    frame[row * width + x] = *color;
+                }
+                last = now;
+            }
+        }
+    }
+}
+
+impl<const N: usize> CaptureSink for Scope<N> {
+    fn capture(&mut self, channel: usize, samples: &[i16]) {
+        let samples = &samples[samples.len().saturating_sub(N)..];
+        for sample in samples {
+            self.samples[channel][self.heads[channel]] = *sample;
+            self.heads[channel] = (self.heads[channel] + 1) % N;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+
+    #[test]
+    fn traces_land_in_their_own_lanes() {
+        let mut scope = Scope::<32>::new();
+        scope.capture(0, &[i16::MAX; 32]);
+        scope.capture(3, &[i16::MIN; 32]);
+
+        let mut frame = [0u16; WIDTH * 8];
+        scope.render(&mut frame, WIDTH);
+
+        // Top of lane 0, bottom of lane 3, and the silent ones across the middle
+        assert!(frame[..WIDTH].iter().all(|p| *p == SCOPE_COLORS[0]));
+        assert!(frame[7 * WIDTH..].iter().all(|p| *p == SCOPE_COLORS[3]));
+        assert!(frame[2 * WIDTH..3 * WIDTH]
+            .iter()
+            .all(|p| *p == SCOPE_COLORS[1]));
+        assert!(frame[WIDTH..2 * WIDTH].iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn trace_starts_on_a_rising_edge() {
+        let mut scope = Scope::<32>::new();
+        let square: [i16; 32] =
+            core::array::from_fn(|i| if (i + 3) % 8 < 4 { -1000 } else { 1000 });
+        scope.capture(0, &square);
+        assert_eq!(scope.trigger(0), 1);
+        assert!(scope.sample(0, 1) > 0 && scope.sample(0, 0) < 0);
+    }
+
+    #[test]
+    fn keeps_only_the_newest() {
+        let mut scope = Scope::<4>::new();
+        scope.capture(1, &[1, 2, 3]);
+        scope.capture(1, &[4, 5, 6, 7, 8, 9]);
+        let kept: [i16; 4] = core::array::from_fn(|i| scope.sample(1, i));
+        assert_eq!(kept, [6, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,197 @@
+// Sample bookkeeping for a DAC that's fed by DMA, kept apart from the registers so it runs on
+// the host too
+
+use super::sink::{AudioSink, RingBuffer};
+
+/// Full volume. Anything above gets clamped.
+pub const MAX_VOLUME: u8 = 16;
+
+/// What the DAC sits at with nothing playing, half way up its range
+const MIDSCALE: i32 = 0x8000;
+
+/// Two halves of `N` DAC codes that the DMA loops over, playing one while the other gets
+/// refilled, with a queue of `Q` samples in front of them to soak up the mixer's bursts.
+///
+/// Codes are unsigned and left adjusted, so a 12-bit DAC just ignores the low nibble.
+///
+/// When the queue runs dry the output fades down from the last sample rather than jumping to
+/// the middle, which would click. It then stays quiet until there's a whole half queued up, so
+/// a mixer that's only just keeping up stutters now and then instead of all the time.
+pub struct DoubleBuffer<const N: usize, const Q: usize> {
+    halves: [[u16; N]; 2],
+    /// Half the DMA was reading as of the last `service`
+    playing: usize,
+    queue: RingBuffer<Q>,
+    volume: u8,
+    /// Last level that went out, after volume
+    last: i32,
+    /// Ran dry and waiting for a half's worth before playing again
+    starved: bool,
+    underruns: u32,
+}
+
+impl<const N: usize, const Q: usize> DoubleBuffer<N, Q> {
+    pub const fn new() -> Self {
+        Self {
+            halves: [[MIDSCALE as u16; N]; 2],
+            playing: 0,
+            queue: RingBuffer::new(),
+            volume: MAX_VOLUME,
+            last: 0,
+            starved: true,
+            underruns: 0,
+        }
+    }
+
+    /// Where the DMA reads `half` from. Neither moves once it's running.
+    #[inline]
+    pub fn half(&self, half: usize) -> &[u16; N] {
+        &self.halves[half]
+    }
+
+    #[inline]
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// Sets the volume, 0 to `MAX_VOLUME`. Samples already in the halves keep the old one.
+    #[inline]
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(MAX_VOLUME);
+    }
+
+    /// Times the queue ran dry part way through refilling a half.
+    #[inline]
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    /// Samples waiting to go into a half.
+    #[inline]
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Tells us which half the DMA is reading now. If it's moved on since last time, the one it
+    /// left is refilled and true comes back.
+    ///
+    /// This has to be called at least once per half, or the DMA laps us and plays a half over
+    /// again.
+    pub fn service(&mut self, playing: usize) -> bool {
+        if playing == self.playing {
+            return false;
+        }
+        let done = self.playing;
+        self.playing = playing;
+        self.refill(done);
+        true
+    }
+
+    fn refill(&mut self, half: usize) {
+        if self.starved && self.queue.len() >= N {
+            self.starved = false;
+        }
+
+        let mut underran = false;
+        for slot in &mut self.halves[half] {
+            let sample = if self.starved { None } else { self.queue.pop() };
+            self.last = match sample {
+                Some(sample) => sample as i32 * self.volume as i32 / MAX_VOLUME as i32,
+                None => {
+                    underran = true;
+                    self.last * 31 / 32
+                }
+            };
+            *slot = (self.last + MIDSCALE) as u16;
+        }
+
+        if underran && !self.starved {
+            self.starved = true;
+            self.underruns += 1;
+        }
+    }
+}
+
+/// Takes mono samples only, the DAC is a single channel.
+impl<const N: usize, const Q: usize> AudioSink for DoubleBuffer<N, Q> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        self.queue.write(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Buffer = DoubleBuffer<4, 16>;
+
+    fn codes(samples: [i32; 4]) -> [u16; 4] {
+        samples.map(|s| (s + MIDSCALE) as u16)
+    }
+
+    #[test]
+    fn waits_for_a_half_then_plays_in_order() {
+        let mut buffer = Buffer::new();
+        buffer.write(&[100, 200, 300]);
+        assert!(buffer.service(1));
+        assert_eq!(*buffer.half(0), codes([0; 4]));
+        // Starting out quiet isn't an underrun
+        assert_eq!(buffer.underruns(), 0);
+
+        buffer.write(&[400, -100, -200]);
+        assert!(buffer.service(0));
+        assert_eq!(*buffer.half(1), codes([100, 200, 300, 400]));
+        assert_eq!(buffer.queued(), 2);
+    }
+
+    #[test]
+    fn same_half_twice_leaves_things_be() {
+        let mut buffer = Buffer::new();
+        buffer.write(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(buffer.service(1));
+        assert!(!buffer.service(1));
+        assert_eq!(buffer.queued(), 4);
+    }
+
+    #[test]
+    fn volume_scales() {
+        let mut buffer = Buffer::new();
+        buffer.set_volume(8);
+        buffer.write(&[1000, -1000, i16::MAX, i16::MIN]);
+        buffer.service(1);
+        assert_eq!(*buffer.half(0), codes([500, -500, 16383, -16384]));
+
+        buffer.set_volume(0);
+        buffer.write(&[1000, -1000, i16::MAX, i16::MIN]);
+        buffer.service(0);
+        assert_eq!(*buffer.half(1), codes([0; 4]));
+
+        buffer.set_volume(200);
+        assert_eq!(buffer.volume(), MAX_VOLUME);
+    }
+
+    #[test]
+    fn underrun_fades_then_waits_for_a_full_half() {
+        let mut buffer = Buffer::new();
+        buffer.write(&[3200, 3200, 3200, 3200, 3200, 3200]);
+        buffer.service(1);
+        buffer.service(0);
+        assert_eq!(buffer.underruns(), 1);
+
+        // Two real samples, then heading back down without a jump
+        let half = *buffer.half(1);
+        assert_eq!(half[..2], codes([3200; 4])[..2]);
+        assert_eq!(half[2], (3100 + MIDSCALE) as u16);
+        assert!(half[3] < half[2] && half[3] > MIDSCALE as u16);
+
+        // Not enough for a whole half, so still quiet
+        buffer.write(&[3200, 3200]);
+        buffer.service(1);
+        assert!(buffer.half(0).iter().all(|c| *c < half[3]));
+        assert_eq!(buffer.underruns(), 1);
+
+        buffer.write(&[3200, 3200]);
+        buffer.service(0);
+        assert_eq!(*buffer.half(1), codes([3200; 4]));
+    }
+}
@@ -0,0 +1,501 @@
+// Turns the channels' levels into samples at whatever rate the output wants
+
+use super::capture::CaptureSink;
+use super::sink::AudioSink;
+use super::Apu;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    /// Both sides summed, for the board's single DAC
+    Mono,
+    /// Interleaved left/right
+    Stereo,
+}
+
+const PHASE_BITS: u32 = 4;
+const TAPS: usize = 8;
+const KERNEL_SHIFT: u32 = 12;
+
+/// Band-limited impulses, windowed sinc cut off a little under the output's Nyquist
+/// frequency, one row per sixteenth of a sample the change lands on. Each row sums to
+/// `1 << KERNEL_SHIFT`, so integrating them turns every change in level into a step without
+/// the aliasing a hard edge would have.
+const IMPULSES: [[i32; TAPS]; 1 << PHASE_BITS] = [
+    [23, -130, 312, 3686, 312, -130, 23, 0],
+    [17, -87, 126, 3663, 523, -177, 31, 0],
+    [11, -49, -33, 3597, 758, -226, 38, 0],
+    [7, -16, -163, 3485, 1013, -275, 46, -1],
+    [3, 11, -266, 3333, 1284, -321, 53, -1],
+    [1, 32, -342, 3146, 1565, -363, 59, -2],
+    [-1, 48, -393, 2926, 1852, -397, 63, -2],
+    [-2, 58, -422, 2681, 2138, -420, 65, -2],
+    [-2, 63, -430, 2417, 2417, -430, 63, -2],
+    [-2, 65, -420, 2137, 2682, -422, 58, -2],
+    [-2, 63, -397, 1851, 2927, -393, 48, -1],
+    [-2, 59, -363, 1565, 3146, -342, 32, 1],
+    [-1, 53, -321, 1284, 3333, -266, 11, 3],
+    [-1, 46, -275, 1013, 3485, -163, -16, 7],
+    [0, 38, -226, 759, 3596, -33, -49, 11],
+    [0, 31, -177, 522, 3664, 126, -87, 17],
+];
+
+/// Output samples that can pile up between drains. Any more and the oldest get thrown away.
+const BUFFER: usize = 1024;
+
+/// Sample value per unit of mixed level. The loudest a side gets is four channels at 15 times
+/// a master volume of 8, which this keeps just inside an i16.
+const LEVEL_SCALE: i32 = 64;
+
+/// How much charge the output capacitor keeps per T-cycle.
+const CHARGE: f32 = 0.999958;
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Captured sample value per unit of a channel's level, which runs -15 to 15.
+const CAPTURE_SCALE: i16 = 2048;
+
+/// One output side's worth of resampler and filter.
+struct Side {
+    /// Level changes, spread out by the impulses and waiting to be integrated
+    deltas: [i32; BUFFER + TAPS],
+    /// Level as of the last change
+    level: i32,
+    integrator: i32,
+    capacitor: f32,
+}
+
+impl Side {
+    const fn new() -> Self {
+        Self {
+            deltas: [0; BUFFER + TAPS],
+            level: 0,
+            integrator: 0,
+            capacitor: 0.0,
+        }
+    }
+
+    /// Moves to `level` at `time`, in output samples with 32 fractional bits.
+    #[inline]
+    fn set(&mut self, time: u64, level: i32) {
+        let delta = level - self.level;
+        if delta == 0 {
+            return;
+        }
+        self.level = level;
+
+        let at = (time >> 32) as usize;
+        let phase = ((time >> (32 - PHASE_BITS)) & ((1 << PHASE_BITS) - 1)) as usize;
+        for (slot, weight) in self.deltas[at..at + TAPS].iter_mut().zip(&IMPULSES[phase]) {
+            *slot += delta * weight;
+        }
+    }
+
+    /// Integrates the next sample and runs it through the capacitor.
+    #[inline]
+    fn sample(&mut self, i: usize, charge: f32) -> i16 {
+        self.integrator += self.deltas[i];
+        let input = (self.integrator >> KERNEL_SHIFT) as f32;
+        let output = input - self.capacitor;
+        self.capacitor = input - output * charge;
+        output.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+
+    /// Drops the first `count` samples, which have been read.
+    fn consume(&mut self, count: usize) {
+        self.deltas.copy_within(count.., 0);
+        let kept = self.deltas.len() - count;
+        self.deltas[kept..].fill(0);
+    }
+}
+
+/// Applies NR51's panning and NR50's master volume to the channels, then resamples the result
+/// from the T-cycle clock down to the output rate.
+///
+/// The output goes through a high-pass filter standing in for the capacitor on the real
+/// thing's output. A DAC that's on but idle sits at one end of its range, so without it any
+/// lopsided wave drifts off centre.
+pub struct Mixer {
+    layout: Layout,
+    rate: u32,
+    /// Output samples since the start of `deltas`, with 32 fractional bits
+    time: u64,
+    /// How far `time` moves per T-cycle
+    step: u64,
+    /// Capacitor charge kept per output sample
+    charge: f32,
+    sides: [Side; 2],
+    /// Bit per channel left out of the mix
+    muted: u8,
+    capturing: bool,
+    /// Each channel's level for every output sample so far, when capturing
+    captured: [[i8; 4]; BUFFER],
+    /// Level each channel has been at since the last `advance`
+    held: [i8; 4],
+    /// Output samples `captured` has been filled up to
+    recorded: usize,
+}
+
+impl Mixer {
+    pub fn new(rate: u32, layout: Layout) -> Self {
+        // A T-cycle's charge factor raised to the T-cycles per sample, without `powf`
+        let mut charge = 1.0;
+        for _ in 0..CYCLES_PER_SECOND / rate {
+            charge *= CHARGE;
+        }
+
+        Self {
+            layout,
+            rate,
+            time: 0,
+            // 4194304 is 1 << 22, which makes this exact
+            step: ((rate as u64) << 32) / CYCLES_PER_SECOND as u64,
+            charge,
+            sides: [Side::new(), Side::new()],
+            muted: 0,
+            capturing: false,
+            captured: [[0; 4]; BUFFER],
+            held: [0; 4],
+            recorded: 0,
+        }
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    #[inline]
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Whether `channel`, 0-3, is left out of the mix.
+    #[inline]
+    pub fn muted(&self, channel: usize) -> bool {
+        self.muted & (1 << channel) != 0
+    }
+
+    /// Leaves `channel`, 0-3, out of the mix or puts it back. Captures still get it either way.
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        if muted {
+            self.muted |= 1 << channel;
+        } else {
+            self.muted &= !(1 << channel);
+        }
+    }
+
+    #[inline]
+    pub fn capturing(&self) -> bool {
+        self.capturing
+    }
+
+    /// Starts or stops recording each channel's level for `drain_with_capture`. Samples from
+    /// before it started that haven't been drained yet get the levels as they are now.
+    pub fn set_capturing(&mut self, capturing: bool) {
+        if capturing && !self.capturing {
+            let now = self.available();
+            self.captured[..now].fill(self.held);
+            self.recorded = now;
+        }
+        self.capturing = capturing;
+    }
+
+    /// Each channel's level right now, -15 to 15. Digital 0-15 lands on the analog side like
+    /// that, and a DAC that's off is 0.
+    fn channels(apu: &Apu) -> [i8; 4] {
+        if !apu.powered() {
+            return [0; 4];
+        }
+        let outputs = apu.outputs();
+        let dacs = apu.dacs();
+        core::array::from_fn(|ch| {
+            if dacs[ch] {
+                outputs[ch] as i8 * 2 - 15
+            } else {
+                0
+            }
+        })
+    }
+
+    /// What the left and right outputs are with `channels` at those levels.
+    fn levels(&self, apu: &Apu, channels: [i8; 4]) -> (i32, i32) {
+        let nr51 = apu.nr51();
+        let (mut left, mut right) = (0, 0);
+        for (ch, level) in channels.iter().enumerate() {
+            if self.muted(ch) {
+                continue;
+            }
+            let level = *level as i32;
+            if nr51 & (0x10 << ch) != 0 {
+                left += level;
+            }
+            if nr51 & (0x01 << ch) != 0 {
+                right += level;
+            }
+        }
+
+        let nr50 = apu.nr50();
+        left *= ((nr50 >> 4) & 0x07) as i32 + 1;
+        right *= (nr50 & 0x07) as i32 + 1;
+        (left * LEVEL_SCALE, right * LEVEL_SCALE)
+    }
+
+    /// Moves time on by `cycles` T-cycles, which `apu` just ran for, and picks up where its
+    /// channels ended up.
+    pub fn advance(&mut self, apu: &Apu, cycles: u32) {
+        self.time += self.step * cycles as u64;
+        let overrun = self.available().saturating_sub(BUFFER);
+        if overrun > 0 {
+            // Nobody's draining us, keep the newest
+            self.drain_into(&mut Discard, None::<&mut Discard>, overrun);
+        }
+
+        let channels = Self::channels(apu);
+        if self.capturing {
+            // Every sample that finished since last time had the old levels the whole way
+            let now = self.available();
+            self.captured[self.recorded..now].fill(self.held);
+            self.recorded = now;
+        }
+        self.held = channels;
+
+        let (left, right) = self.levels(apu, channels);
+        match self.layout {
+            Layout::Mono => self.sides[0].set(self.time, (left + right) / 2),
+            Layout::Stereo => {
+                self.sides[0].set(self.time, left);
+                self.sides[1].set(self.time, right);
+            }
+        }
+    }
+
+    /// Output samples ready to go.
+    #[inline]
+    pub fn available(&self) -> usize {
+        (self.time >> 32) as usize
+    }
+
+    /// Sends every finished sample to `sink`. Whatever it has no room for is lost, as is
+    /// anything captured.
+    pub fn drain<S: AudioSink + ?Sized>(&mut self, sink: &mut S) {
+        self.drain_into(sink, None::<&mut Discard>, self.available());
+    }
+
+    /// Like `drain`, but also hands `capture` each channel's levels for the same samples, one
+    /// channel at a time. Nothing goes to it unless capturing's on.
+    pub fn drain_with_capture<S: AudioSink + ?Sized, C: CaptureSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        capture: &mut C,
+    ) {
+        self.drain_into(sink, Some(capture), self.available());
+    }
+
+    /// Sends the oldest `count` samples to `sink`. That can be more than `BUFFER` when an
+    /// overrun throws a long stretch away, which goes a buffer at a time.
+    fn drain_into<S: AudioSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        capture: Option<&mut (impl CaptureSink + ?Sized)>,
+        count: usize,
+    ) {
+        let mut chunk = [0i16; 64];
+        let mut filled = 0;
+
+        let mut left = count;
+        while left > 0 {
+            let part = left.min(BUFFER);
+            for i in 0..part {
+                match self.layout {
+                    Layout::Mono => {
+                        chunk[filled] = self.sides[0].sample(i, self.charge);
+                        filled += 1;
+                    }
+                    Layout::Stereo => {
+                        chunk[filled] = self.sides[0].sample(i, self.charge);
+                        chunk[filled + 1] = self.sides[1].sample(i, self.charge);
+                        filled += 2;
+                    }
+                }
+                if filled == chunk.len() {
+                    sink.write(&chunk);
+                    filled = 0;
+                }
+            }
+            for side in &mut self.sides {
+                side.consume(part);
+            }
+            left -= part;
+        }
+        if filled > 0 {
+            sink.write(&chunk[..filled]);
+        }
+
+        let drained = self.recorded.min(count);
+        if let Some(capture) = capture {
+            let captured = &self.captured[..drained];
+            for ch in 0..4 {
+                for chunk in captured.chunks(64) {
+                    let mut samples = [0i16; 64];
+                    for (sample, levels) in samples.iter_mut().zip(chunk) {
+                        *sample = levels[ch] as i16 * CAPTURE_SCALE;
+                    }
+                    capture.capture(ch, &samples[..chunk.len()]);
+                }
+            }
+        }
+        self.captured.copy_within(drained..self.recorded, 0);
+        self.recorded -= drained;
+
+        self.time -= (count as u64) << 32;
+    }
+}
+
+/// Throws samples away, for when the buffer overruns.
+struct Discard;
+
+impl AudioSink for Discard {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        samples.len()
+    }
+}
+
+impl CaptureSink for Discard {
+    fn capture(&mut self, _channel: usize, _samples: &[i16]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    impl AudioSink for Vec<i16> {
+        fn write(&mut self, samples: &[i16]) -> usize {
+            self.extend_from_slice(samples);
+            samples.len()
+        }
+    }
+
+    const RATE: u32 = 32768;
+    const CYCLES_PER_SAMPLE: u32 = CYCLES_PER_SECOND / RATE;
+
+    /// Runs `apu` for `samples` output samples, a few T-cycles at a time like the CPU would.
+    fn run(apu: &mut Apu, mixer: &mut Mixer, samples: u32) -> Vec<i16> {
+        let mut out = Vec::new();
+        for _ in 0..samples * CYCLES_PER_SAMPLE / 8 {
+            apu.tick(8);
+            mixer.advance(apu, 8);
+            if mixer.available() > 256 {
+                mixer.drain(&mut out);
+            }
+        }
+        mixer.drain(&mut out);
+        out
+    }
+
+    /// Channel 2 playing a 12.5% square at 512 Hz.
+    fn square(nr51: u8) -> Apu {
+        let mut apu = Apu::new(true);
+        apu.write(0xff26, 0x80);
+        apu.write(0xff24, 0x77);
+        apu.write(0xff25, nr51);
+        apu.write(0xff16, 0x00);
+        apu.write(0xff17, 0xF0);
+        apu.write(0xff18, 0x00);
+        apu.write(0xff19, 0x87);
+        apu
+    }
+
+    #[test]
+    fn charge_factor_matches_the_usual_one() {
+        let mixer = Mixer::new(44100, Layout::Mono);
+        assert!((mixer.charge - 0.996).abs() < 0.0005);
+    }
+
+    #[test]
+    fn silence_is_silent() {
+        let mut apu = Apu::new(true);
+        let mut mixer = Mixer::new(RATE, Layout::Stereo);
+        let out = run(&mut apu, &mut mixer, 1000);
+        assert_eq!(out.len(), 2000);
+        assert!(out.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn panning_keeps_sides_apart() {
+        let mut apu = square(0x20);
+        let mut mixer = Mixer::new(RATE, Layout::Stereo);
+        let out = run(&mut apu, &mut mixer, 1000);
+        let left = out
+            .iter()
+            .step_by(2)
+            .map(|s| s.unsigned_abs())
+            .max()
+            .unwrap();
+        let right = out
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|s| s.unsigned_abs())
+            .max()
+            .unwrap();
+        assert!(left > 4000);
+        assert_eq!(right, 0);
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        let mut apu = square(0x22);
+        let mut mixer = Mixer::new(RATE, Layout::Mono);
+        let out = run(&mut apu, &mut mixer, RATE);
+
+        // Mostly low with short spikes, which is a long way off centre until the filter has
+        // had a second to settle
+        let tail = &out[out.len() - 4096..];
+        let mean = tail.iter().map(|s| *s as i64).sum::<i64>() / tail.len() as i64;
+        let peak = tail.iter().map(|s| s.unsigned_abs()).max().unwrap() as i64;
+        assert!(mean.abs() < peak / 20, "mean {} peak {}", mean, peak);
+    }
+
+    #[test]
+    fn overrun_keeps_newest() {
+        let apu = Apu::new(true);
+        let mut mixer = Mixer::new(RATE, Layout::Mono);
+        for _ in 0..BUFFER as u32 * 3 {
+            mixer.advance(&apu, CYCLES_PER_SAMPLE);
+        }
+        assert_eq!(mixer.available(), BUFFER);
+    }
+
+    #[test]
+    fn big_advance_into_a_nearly_full_buffer() {
+        let mut apu = Apu::new(true);
+        let mut mixer = Mixer::new(RATE, Layout::Stereo);
+        for _ in 0..1000 {
+            apu.tick(CYCLES_PER_SAMPLE);
+            mixer.advance(&apu, CYCLES_PER_SAMPLE);
+        }
+
+        // The square starts 1000 samples in, right before an HDMA stall hands over a few
+        // thousand T-cycles in one go
+        let mut apu = square(0x22);
+        mixer.advance(&apu, CYCLES_PER_SAMPLE);
+        apu.tick(4096);
+        mixer.advance(&apu, 4096);
+        assert_eq!(mixer.available(), BUFFER);
+
+        let mut out = Vec::new();
+        mixer.drain(&mut out);
+        assert_eq!(out.len(), BUFFER * 2);
+        // Only the oldest few went, the square's still in there
+        let start = out.iter().position(|s| *s != 0).unwrap() / 2;
+        assert!((980..1000).contains(&start), "starts at {}", start);
+
+        // A second at once, only the last buffer of it stays
+        mixer.advance(&apu, CYCLES_PER_SECOND);
+        assert_eq!(mixer.available(), BUFFER);
+    }
+}
@@ -0,0 +1,297 @@
+// Audio processing unit, 0xFF10-0xFF3F
+
+mod capture;
+mod double_buffer;
+mod mixer;
+mod noise;
+mod pulse;
+pub mod recorder;
+mod sink;
+mod units;
+pub mod wav;
+mod wave;
+
+pub use capture::*;
+pub use double_buffer::*;
+pub use mixer::*;
+pub use sink::*;
+
+use noise::Noise;
+use pulse::Pulse;
+use wave::Wave;
+
+/// T-cycles per frame sequencer step, which runs at 512 Hz
+const FRAME_SEQUENCER_CYCLES: u32 = 4_194_304 / 512;
+
+const NR52_POWER: u8 = 0b1000_0000;
+
+/// Bits that always read back as 1, for NR10 through NR52. Write-only registers read as all
+/// ones, as do the gaps.
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+pub struct Apu {
+    cgb: bool,
+    powered: bool,
+    /// Last value written to each of NR10-NR51, for reading back
+    regs: [u8; 0x16],
+    ch1: Pulse,
+    ch2: Pulse,
+    ch3: Wave,
+    ch4: Noise,
+    /// Step the frame sequencer takes next, 0-7
+    frame_step: u8,
+    /// T-cycles until it does
+    frame_timer: u32,
+}
+
+impl Apu {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            powered: false,
+            regs: [0; 0x16],
+            ch1: Pulse::new(true),
+            ch2: Pulse::new(false),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            frame_step: 0,
+            frame_timer: FRAME_SEQUENCER_CYCLES,
+        }
+    }
+
+    /// Each channel's output level, 0-15, silent ones included.
+    #[inline]
+    pub fn outputs(&self) -> [u8; 4] {
+        [
+            self.ch1.output(),
+            self.ch2.output(),
+            self.ch3.output(),
+            self.ch4.output(),
+        ]
+    }
+
+    /// Which channels have their DAC on. A DAC that's off outputs nothing at all, rather than
+    /// the level a 0 would get.
+    #[inline]
+    pub fn dacs(&self) -> [bool; 4] {
+        [
+            self.ch1.dac_on(),
+            self.ch2.dac_on(),
+            self.ch3.dac_on(),
+            self.ch4.dac_on(),
+        ]
+    }
+
+    #[inline]
+    pub fn powered(&self) -> bool {
+        self.powered
+    }
+
+    /// NR50, master volume and VIN.
+    #[inline]
+    pub fn nr50(&self) -> u8 {
+        self.regs[0x14]
+    }
+
+    /// NR51, which channel goes to which side.
+    #[inline]
+    pub fn nr51(&self) -> u8 {
+        self.regs[0x15]
+    }
+
+    /// Advances the channels and frame sequencer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.powered {
+            return;
+        }
+        self.ch1.tick(cycles);
+        self.ch2.tick(cycles);
+        self.ch3.tick(cycles);
+        self.ch4.tick(cycles);
+
+        let mut cycles = cycles;
+        while cycles >= self.frame_timer {
+            cycles -= self.frame_timer;
+            self.frame_timer = FRAME_SEQUENCER_CYCLES;
+            self.step_frame_sequencer();
+        }
+        self.frame_timer -= cycles;
+    }
+
+    /// Length on even steps, sweep on 2 and 6, envelopes on 7.
+    fn step_frame_sequencer(&mut self) {
+        let step = self.frame_step;
+        self.frame_step = (step + 1) & 0x07;
+
+        if step & 1 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+    }
+
+    #[inline]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff26 => {
+                let channels = [
+                    self.ch1.enabled(),
+                    self.ch2.enabled(),
+                    self.ch3.enabled(),
+                    self.ch4.enabled(),
+                ];
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |bits, (i, on)| bits | ((*on as u8) << i));
+                READ_MASKS[0x16] | if self.powered { NR52_POWER } else { 0 } | status
+            }
+            0xff10..=0xff25 => {
+                let i = (addr - 0xff10) as usize;
+                self.regs[i] | READ_MASKS[i]
+            }
+            0xff30..=0xff3f => self.ch3.read_ram((addr - 0xff30) as usize),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff26 => self.write_power(val & NR52_POWER != 0),
+            0xff30..=0xff3f => self.ch3.write_ram((addr - 0xff30) as usize, val),
+            // The DMG keeps its length counters powered, so those can still be loaded
+            0xff10..=0xff25 if !self.powered && !self.cgb => match addr {
+                0xff11 => self.ch1.write_length(val & 0x3F),
+                0xff16 => self.ch2.write_length(val & 0x3F),
+                0xff1b => self.ch3.write_length(val),
+                0xff20 => self.ch4.write_length(val & 0x3F),
+                _ => {}
+            },
+            // ...but nothing else takes writes while it's off
+            0xff10..=0xff25 if !self.powered => {}
+            0xff10..=0xff25 => {
+                self.regs[(addr - 0xff10) as usize] = val;
+                // Enabling length on NRx4 clocks it early if the next step won't
+                let extra = self.frame_step & 1 == 1;
+                match addr {
+                    0xff10..=0xff14 => self.ch1.write(addr - 0xff10, val, extra),
+                    0xff15..=0xff19 => self.ch2.write(addr - 0xff15, val, extra),
+                    0xff1a..=0xff1e => self.ch3.write(addr - 0xff1a, val, extra),
+                    0xff1f..=0xff23 => self.ch4.write(addr - 0xff1f, val, extra),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Powering off clears every register and keeps them that way until it's back on. Wave RAM
+    /// survives, and on DMG so do the length counters.
+    fn write_power(&mut self, on: bool) {
+        if self.powered && !on {
+            let keep_length = !self.cgb;
+            self.regs = [0; 0x16];
+            self.ch1.power_off(keep_length);
+            self.ch2.power_off(keep_length);
+            self.ch3.power_off(keep_length);
+            self.ch4.power_off(keep_length);
+        } else if !self.powered && on {
+            self.frame_step = 0;
+            self.frame_timer = FRAME_SEQUENCER_CYCLES;
+        }
+        self.powered = on;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered(cgb: bool) -> Apu {
+        let mut apu = Apu::new(cgb);
+        apu.write(0xff26, 0x80);
+        apu
+    }
+
+    #[test]
+    fn registers_read_back_through_masks() {
+        let mut apu = powered(true);
+        for addr in 0xff10..=0xff25 {
+            apu.write(addr, 0x00);
+        }
+        let reads: [u8; 0x16] = core::array::from_fn(|i| apu.read(0xff10 + i as u16));
+        assert_eq!(reads[..], READ_MASKS[..0x16]);
+        assert_eq!(apu.read(0xff26), 0xF0);
+        assert_eq!(apu.read(0xff27), 0xFF);
+    }
+
+    #[test]
+    fn power_off_clears_and_locks_registers() {
+        let mut apu = powered(true);
+        apu.write(0xff24, 0x77);
+        apu.write(0xff30, 0x12);
+        apu.write(0xff26, 0x00);
+        assert_eq!(apu.read(0xff24), 0x00);
+        assert_eq!(apu.read(0xff26), 0x70);
+
+        apu.write(0xff24, 0x77);
+        assert_eq!(apu.read(0xff24), 0x00);
+        assert_eq!(apu.read(0xff30), 0x12);
+    }
+
+    #[test]
+    fn triggered_channel_shows_in_nr52_until_length_runs_out() {
+        let mut apu = powered(true);
+        apu.write(0xff12, 0xF0);
+        apu.write(0xff11, 0x3E);
+        apu.write(0xff14, 0xC0);
+        assert_eq!(apu.read(0xff26), 0xF1);
+
+        // Two length clocks, on steps 0 and 2
+        apu.tick(FRAME_SEQUENCER_CYCLES * 3);
+        assert_eq!(apu.read(0xff26), 0xF0);
+    }
+
+    #[test]
+    fn dac_off_kills_channel() {
+        let mut apu = powered(true);
+        apu.write(0xff17, 0xF0);
+        apu.write(0xff19, 0x80);
+        assert_eq!(apu.read(0xff26) & 0x02, 0x02);
+        apu.write(0xff17, 0x00);
+        assert_eq!(apu.read(0xff26) & 0x02, 0x00);
+
+        // And triggering with it off doesn't start it
+        apu.write(0xff19, 0x80);
+        assert_eq!(apu.read(0xff26) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn dmg_length_writable_while_off() {
+        let mut apu = powered(false);
+        apu.write(0xff26, 0x00);
+        apu.write(0xff11, 0x3F);
+        apu.write(0xff26, 0x80);
+        apu.write(0xff12, 0xF0);
+        apu.write(0xff14, 0xC0);
+
+        // A single length clock left
+        apu.tick(FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read(0xff26) & 0x01, 0);
+    }
+}
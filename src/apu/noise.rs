@@ -0,0 +1,159 @@
+// Channel 4, white noise out of a linear feedback shift register
+
+use super::units::{Envelope, Length};
+
+/// T-cycles between LFSR clocks for each NR43 divisor code, before the shift.
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct Noise {
+    length: Length,
+    envelope: Envelope,
+    enabled: bool,
+    shift: u8,
+    /// 7-bit mode, which makes the noise much more tonal
+    short: bool,
+    divisor: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    pub const fn new() -> Self {
+        Self {
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            enabled: false,
+            shift: 0,
+            short: false,
+            divisor: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+        }
+    }
+
+    #[inline]
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn dac_on(&self) -> bool {
+        self.envelope.dac_on()
+    }
+
+    #[inline]
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+
+    /// Writes NR41-NR44, `reg` being the 1-4 part. See `Length::write_control` for `extra`.
+    pub fn write(&mut self, reg: u16, val: u8, extra: bool) {
+        match reg {
+            1 => self.length.load(val),
+            2 => {
+                self.envelope.write(val);
+                if !self.dac_on() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = val >> 4;
+                self.short = val & 0x08 != 0;
+                self.divisor = val & 0x07;
+            }
+            4 => {
+                if self.length.write_control(val, extra) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac_on();
+                    self.lfsr = 0x7FFF;
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[inline]
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    #[inline]
+    fn step(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.short {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        // Shifts of 14 and 15 don't clock the LFSR at all
+        if !self.enabled || self.shift >= 14 {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.step();
+        }
+        self.timer -= cycles;
+    }
+
+    #[inline]
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    #[inline]
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = core::mem::replace(&mut self.length, Length::new(64));
+        *self = Self::new();
+        if keep_length {
+            self.length = length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn period_of(short: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.short = short;
+        let start = noise.lfsr;
+        let mask = if short { 0x7F } else { 0x7FFF };
+        (1..=0x8000)
+            .find(|_| {
+                noise.step();
+                noise.lfsr & mask == start & mask
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn lfsr_periods() {
+        assert_eq!(period_of(true), 127);
+        assert_eq!(period_of(false), 32767);
+    }
+}
@@ -0,0 +1,303 @@
+// Square channels 1 and 2, with channel 1's frequency sweep
+
+use super::units::{Envelope, Length};
+
+/// Waveforms for the four NRx1 duty settings, one bit per step.
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+enum SweepResult {
+    Unchanged,
+    /// New frequency, and whether the check after it overflowed
+    Frequency(u16, bool),
+    Overflow,
+}
+
+/// NR10. Recomputes the frequency from a shadow copy every `period` 128 Hz ticks.
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+    /// A subtraction happened since the last trigger, clearing negate now kills the channel
+    negated: bool,
+}
+
+impl Sweep {
+    const fn new() -> Self {
+        Self {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow: 0,
+            negated: false,
+        }
+    }
+
+    /// Returns false if the write turns the channel off.
+    fn write(&mut self, val: u8) -> bool {
+        self.period = (val >> 4) & 0x07;
+        self.negate = val & 0x08 != 0;
+        self.shift = val & 0x07;
+        self.negate || !self.negated
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Returns false if the first overflow check turns the channel right back off.
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.negated = false;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.shift == 0 || self.calculate() <= 2047
+    }
+
+    fn clock(&mut self) -> SweepResult {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return SweepResult::Unchanged;
+        }
+        self.timer = if self.period == 0 { 8 } else { self.period };
+        if !self.enabled || self.period == 0 {
+            return SweepResult::Unchanged;
+        }
+
+        let frequency = self.calculate();
+        if frequency > 2047 {
+            return SweepResult::Overflow;
+        }
+        if self.shift == 0 {
+            return SweepResult::Unchanged;
+        }
+        self.shadow = frequency;
+        // Checked again with the new frequency, but only to see if it overflows
+        let overflow = self.calculate() > 2047;
+        SweepResult::Frequency(frequency, overflow)
+    }
+}
+
+pub struct Pulse {
+    sweep: Option<Sweep>,
+    length: Length,
+    envelope: Envelope,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    frequency: u16,
+    /// T-cycles until the next duty step
+    timer: u32,
+}
+
+impl Pulse {
+    /// Channel 1 if `sweep`, channel 2 otherwise.
+    pub const fn new(sweep: bool) -> Self {
+        Self {
+            sweep: if sweep { Some(Sweep::new()) } else { None },
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            enabled: false,
+            duty: 0,
+            step: 0,
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    #[inline]
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn dac_on(&self) -> bool {
+        self.envelope.dac_on()
+    }
+
+    /// Current output level, 0-15.
+    #[inline]
+    pub fn output(&self) -> u8 {
+        if !self.enabled || (DUTY[self.duty as usize] >> self.step) & 1 == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+
+    /// Writes NRx0-NRx4, `reg` being the x0-x4 part. See `Length::write_control` for `extra`.
+    pub fn write(&mut self, reg: u16, val: u8, extra: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    if !sweep.write(val) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val);
+            }
+            2 => {
+                self.envelope.write(val);
+                if !self.dac_on() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+                if self.length.write_control(val, extra) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// NRx1's length bits, which DMGs let through even with the APU off.
+    #[inline]
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_on();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.step = (self.step + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    #[inline]
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    #[inline]
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let result = match &mut self.sweep {
+            Some(sweep) => sweep.clock(),
+            None => return,
+        };
+        match result {
+            SweepResult::Unchanged => {}
+            SweepResult::Frequency(frequency, overflow) => {
+                self.frequency = frequency;
+                if overflow {
+                    self.enabled = false;
+                }
+            }
+            SweepResult::Overflow => self.enabled = false,
+        }
+    }
+
+    /// What powering the APU off leaves behind. `keep_length` is the DMG's quirk.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = core::mem::replace(&mut self.length, Length::new(64));
+        *self = Self::new(self.sweep.is_some());
+        if keep_length {
+            self.length = length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_cycle_steps_with_frequency() {
+        let mut pulse = Pulse::new(false);
+        pulse.write(1, 0x80, false);
+        pulse.write(2, 0xF0, false);
+        pulse.write(3, 0xFF, false);
+        pulse.write(4, 0x87, false);
+
+        // 50% duty, high for four of the eight steps. Frequency 0x7FF steps every
+        // (2048 - 0x7FF) * 4 T-cycles
+        let period = 4;
+        let mut highs = 0;
+        for _ in 0..8 {
+            pulse.tick(period);
+            if pulse.output() == 15 {
+                highs += 1;
+            }
+        }
+        assert_eq!(highs, 4);
+    }
+
+    #[test]
+    fn sweep_overflow_disables() {
+        let mut pulse = Pulse::new(true);
+        pulse.write(0, 0x11, false);
+        pulse.write(2, 0xF0, false);
+        pulse.write(3, 0x00, false);
+        pulse.write(4, 0x87, false);
+        // 0x700 + 0x380 already overflows on trigger
+        assert!(!pulse.enabled());
+
+        pulse.write(4, 0x83, false);
+        assert!(pulse.enabled());
+        pulse.clock_sweep();
+        assert_eq!(pulse.frequency, 0x480);
+        assert!(pulse.enabled());
+
+        // 0x500 + 0x280 is fine, but the check after it isn't
+        pulse.write(3, 0x00, false);
+        pulse.write(4, 0x85, false);
+        assert!(pulse.enabled());
+        pulse.clock_sweep();
+        assert_eq!(pulse.frequency, 0x780);
+        assert!(!pulse.enabled());
+    }
+
+    #[test]
+    fn clearing_negate_after_use_disables() {
+        let mut pulse = Pulse::new(true);
+        pulse.write(0, 0x19, false);
+        pulse.write(2, 0xF0, false);
+        pulse.write(4, 0x84, false);
+        assert!(pulse.enabled());
+        pulse.write(0, 0x11, false);
+        assert!(!pulse.enabled());
+    }
+}
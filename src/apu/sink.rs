@@ -0,0 +1,91 @@
+// Where mixed samples go
+
+/// Anything that plays or stores signed 16-bit samples, interleaved left/right in stereo.
+pub trait AudioSink {
+    /// Takes as many of `samples` as there's room for and returns how many that was.
+    fn write(&mut self, samples: &[i16]) -> usize;
+}
+
+/// Fixed size FIFO sitting between the mixer and whatever drains it at its own pace. When it
+/// fills up, new samples get dropped rather than old ones overwritten.
+pub struct RingBuffer<const N: usize> {
+    samples: [i16; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn free(&self) -> usize {
+        N - self.len
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<i16> {
+        if self.len == 0 {
+            return None;
+        }
+        let sample = self.samples[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(sample)
+    }
+
+    /// Fills `out` with as many samples as there are, returning how many that was.
+    pub fn read(&mut self, out: &mut [i16]) -> usize {
+        let count = out.len().min(self.len);
+        for slot in &mut out[..count] {
+            *slot = self.samples[self.head];
+            self.head = (self.head + 1) % N;
+        }
+        self.len -= count;
+        count
+    }
+}
+
+impl<const N: usize> AudioSink for RingBuffer<N> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        let count = samples.len().min(self.free());
+        for sample in &samples[..count] {
+            self.samples[(self.head + self.len) % N] = *sample;
+            self.len += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_and_drops_when_full() {
+        let mut ring = RingBuffer::<4>::new();
+        assert_eq!(ring.write(&[1, 2, 3]), 3);
+        assert_eq!(ring.pop(), Some(1));
+        assert_eq!(ring.write(&[4, 5, 6]), 2);
+
+        let mut out = [0; 8];
+        assert_eq!(ring.read(&mut out), 4);
+        assert_eq!(out[..4], [2, 3, 4, 5]);
+        assert!(ring.is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+// Length counter and volume envelope, shared between channels
+
+/// Counts down at 256 Hz and shuts the channel off when it runs out.
+pub struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    pub const fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// NRx1 loads the counter, at any time.
+    #[inline]
+    pub fn load(&mut self, val: u8) {
+        self.counter = self.max - (val as u16 & (self.max - 1));
+    }
+
+    /// Returns true when the counter just ran out and the channel should go off.
+    #[inline]
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    /// Applies the enable and trigger bits of NRx4. `extra` says whether the frame sequencer's
+    /// next step leaves length alone, in which case turning it on clocks it once right away.
+    /// Returns true if that turned the channel off.
+    pub fn write_control(&mut self, val: u8, extra: bool) -> bool {
+        let was = self.enabled;
+        let trigger = val & 0x80 != 0;
+        self.enabled = val & 0x40 != 0;
+
+        let mut off = false;
+        if extra && !was && self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            off = self.counter == 0 && !trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra {
+                self.counter -= 1;
+            }
+        }
+        off
+    }
+}
+
+/// NRx2, ramping the volume up or down every `period` 64 Hz ticks.
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub const fn new() -> Self {
+        Self {
+            initial: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    #[inline]
+    pub fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+
+    /// The DAC is on as long as the top five bits of NRx2 aren't all zero.
+    #[inline]
+    pub fn dac_on(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    #[inline]
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_runs_out() {
+        let mut length = Length::new(64);
+        length.load(62);
+        length.write_control(0x40, false);
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn enabling_length_early_clocks_it() {
+        let mut length = Length::new(64);
+        length.load(63);
+        assert!(length.write_control(0x40, true));
+
+        // Triggering at zero reloads, less the extra clock
+        let mut length = Length::new(64);
+        length.write_control(0xC0, true);
+        assert_eq!(length.counter, 63);
+    }
+
+    #[test]
+    fn envelope_ramps_and_stops() {
+        let mut env = Envelope::new();
+        env.write(0xE9);
+        env.trigger();
+        assert_eq!(env.volume(), 14);
+        env.clock();
+        assert_eq!(env.volume(), 15);
+        env.clock();
+        assert_eq!(env.volume(), 15);
+    }
+}
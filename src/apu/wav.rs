@@ -0,0 +1,31 @@
+// Just enough WAV to get a capture off into an audio editor
+
+/// RIFF header, fmt chunk and the data chunk's header.
+pub const HEADER_SIZE: usize = 44;
+
+/// Header for `samples` 16-bit mono samples at `rate`. The samples go straight after it, little
+/// endian.
+pub fn header(rate: u32, samples: usize) -> [u8; HEADER_SIZE] {
+    let mut h = [0u8; HEADER_SIZE];
+    let put16 = |h: &mut [u8], at: usize, v: u16| h[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    let put32 = |h: &mut [u8], at: usize, v: u32| h[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    let data = samples as u32 * 2;
+
+    h[0..4].copy_from_slice(b"RIFF");
+    put32(&mut h, 4, HEADER_SIZE as u32 - 8 + data);
+    h[8..12].copy_from_slice(b"WAVE");
+
+    h[12..16].copy_from_slice(b"fmt ");
+    put32(&mut h, 16, 16);
+    // PCM, one channel
+    put16(&mut h, 20, 1);
+    put16(&mut h, 22, 1);
+    put32(&mut h, 24, rate);
+    put32(&mut h, 28, rate * 2);
+    put16(&mut h, 32, 2);
+    put16(&mut h, 34, 16);
+
+    h[36..40].copy_from_slice(b"data");
+    put32(&mut h, 40, data);
+    h
+}
@@ -0,0 +1,183 @@
+// Channel 3, playing 32 4-bit samples out of wave RAM
+
+use super::units::Length;
+
+pub struct Wave {
+    ram: [u8; 16],
+    length: Length,
+    enabled: bool,
+    dac: bool,
+    /// NR32's output level: mute, 100%, 50% or 25%
+    level: u8,
+    frequency: u16,
+    /// Sample being played, 0-31
+    position: u8,
+    sample: u8,
+    /// T-cycles until the next sample
+    timer: u32,
+}
+
+impl Wave {
+    pub const fn new() -> Self {
+        Self {
+            ram: [0; 16],
+            length: Length::new(256),
+            enabled: false,
+            dac: false,
+            level: 0,
+            frequency: 0,
+            position: 0,
+            sample: 0,
+            timer: 0,
+        }
+    }
+
+    #[inline]
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn dac_on(&self) -> bool {
+        self.dac
+    }
+
+    #[inline]
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.level == 0 {
+            0
+        } else {
+            self.sample >> (self.level - 1)
+        }
+    }
+
+    /// While the channel plays, the CPU only gets at the byte the channel is reading.
+    #[inline]
+    fn ram_index(&self, offset: usize) -> usize {
+        if self.enabled {
+            (self.position / 2) as usize
+        } else {
+            offset
+        }
+    }
+
+    #[inline]
+    pub fn read_ram(&self, offset: usize) -> u8 {
+        self.ram[self.ram_index(offset)]
+    }
+
+    #[inline]
+    pub fn write_ram(&mut self, offset: usize, val: u8) {
+        let index = self.ram_index(offset);
+        self.ram[index] = val;
+    }
+
+    /// Writes NR30-NR34, `reg` being the 0-4 part. See `Length::write_control` for `extra`.
+    pub fn write(&mut self, reg: u16, val: u8, extra: bool) {
+        match reg {
+            0 => {
+                self.dac = val & 0x80 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(val),
+            2 => self.level = (val >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((val as u16 & 0x07) << 8);
+                if self.length.write_control(val, extra) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    self.position = 0;
+                    // The first sample takes a little longer to come out
+                    self.timer = self.period() + 6;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[inline]
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[(self.position / 2) as usize];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    #[inline]
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Wave RAM survives power off, and on DMG so does the length.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let ram = self.ram;
+        let length = core::mem::replace(&mut self.length, Length::new(256));
+        *self = Self::new();
+        self.ram = ram;
+        if keep_length {
+            self.length = length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_nibbles_high_first() {
+        let mut wave = Wave::new();
+        wave.write_ram(0, 0xA5);
+        wave.write(0, 0x80, false);
+        wave.write(2, 0x20, false);
+        wave.write(3, 0xFF, false);
+        wave.write(4, 0x87, false);
+
+        wave.tick(2 + 6);
+        assert_eq!(wave.output(), 0x5);
+        wave.tick(2);
+        assert_eq!(wave.output(), 0x0);
+
+        // Only the byte under the play position is reachable now
+        assert_eq!(wave.read_ram(7), wave.ram[1]);
+    }
+
+    #[test]
+    fn level_shifts_samples() {
+        let mut wave = Wave::new();
+        wave.sample = 0x0C;
+        wave.enabled = true;
+        wave.level = 3;
+        assert_eq!(wave.output(), 0x03);
+        wave.level = 0;
+        assert_eq!(wave.output(), 0);
+    }
+}
@@ -0,0 +1,25 @@
+// What stops the CPU from carrying on, for whoever's driving it to report
+
+use core::fmt;
+
+/// Something the emulated machine can't carry on from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmuError {
+    /// Ran one of the 11 opcodes the LR35902 doesn't have, which locks it up for good. The
+    /// display and everything else keep running, only the CPU is stuck.
+    IllegalOpcode {
+        /// Where the opcode was
+        pc: u16,
+        opcode: u8,
+    },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode 0x{:02X} at 0x{:04X}", opcode, pc)
+            }
+        }
+    }
+}
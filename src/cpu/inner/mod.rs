@@ -1,12 +1,10 @@
 mod flag_register;
-mod memory_bus;
-mod program_counter;
 mod register;
+mod register_file;
 
 pub use flag_register::*;
-pub use memory_bus::*;
-pub use program_counter::*;
 pub use register::*;
+pub use register_file::*;
 
 pub enum Timing {
     Default,
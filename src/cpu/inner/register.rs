@@ -1,26 +1,11 @@
 use super::super::CPU;
-use crate::cpu::inner::Countd;
 
 pub trait Src<T> {
-    unsafe fn read(&self, cpu: &mut CPU) -> T;
+    fn read(&self, cpu: &mut CPU) -> T;
 }
 
 pub trait Dst<T> {
-    unsafe fn write(&self, cpu: &mut CPU, val: T);
-}
-
-pub trait Registerd {
-    fn is_virtual(&self) -> bool;
-}
-
-pub trait Registerd8 {
-    /// A function that returns a pointer to an 8-bit general-purpose register
-    unsafe fn addr(&mut self, r: &Register) -> *mut u8;
-}
-
-pub trait Registerd16 {
-    /// A function that returns a pointer to a 16-bit virtual register
-    unsafe fn vaddr(&mut self, r: &Register) -> *mut u16;
+    fn write(&self, cpu: &mut CPU, val: T);
 }
 
 /// Wraps a Register so we can recognize it as containing a vram address instead of a value
@@ -28,15 +13,12 @@ pub(crate) struct ZMem<T: Src<u8>>(pub T);
 /// Wraps a Register so we can recognize it as containing a ram  address instead of a value
 pub(crate) struct Mem<T: Src<u16>>(pub T);
 
-/// Lets us construct concise `Instruction`s for our `CPU` to operate on. As you can imagine, they're
-/// tightly coupled and will most likely remain that way.
-pub enum Register {
-    /// Pseudo-register we use to tell the cpu to consume the first byte of the Program Counter.
-    D8,
+/// The 8-bit registers an instruction can name. F isn't one of them, only `POP AF` and the
+/// flag helpers ever touch it.
+#[derive(Clone, Copy)]
+pub enum Reg8 {
     /// The accumulator register, A.
     A,
-    /// Flag register, whose last nibble doesn't matter much.
-    F,
     /// 8-bit general-purpose register, B.
     B,
     /// 8-bit general-purpose register, C.
@@ -49,116 +31,103 @@ pub enum Register {
     H,
     /// 8-bit general-purpose register, L.
     L,
-    /// The 16-bit virtual accumulator register, HL.
-    HL,
-    /// Used to define an implicit increment to HL after using HL.
-    HLi,
-    /// Used to define an implicit decrement to HL after using it.
-    HLd,
+}
+
+/// The 16-bit registers, the pairs plus SP. Between them and `Reg8` we can construct concise
+/// instructions for our `CPU` to operate on. As you can imagine, they're tightly coupled and
+/// will most likely remain that way.
+#[derive(Clone, Copy)]
+pub enum Reg16 {
     /// 16-bit virtual register AF.
     AF,
     /// 16-bit virtual register BC.
     BC,
     /// 16-bit virtual register DE.
     DE,
+    /// The 16-bit virtual accumulator register, HL.
+    HL,
     /// A representation of our Stack Pointer.
     SP,
-    /// Pseudo-register we use to tell the cpu to consume all (two) bytes of the Program Counter.
-    D16,
 }
 
+/// Pseudo-register we use to tell the cpu to consume the first byte of the Program Counter.
+pub struct D8;
+
+/// Pseudo-register we use to tell the cpu to consume all (two) bytes of the Program Counter.
+pub struct D16;
+
 // TODO DOC ALL of this
 
-impl Registerd for Register {
-    fn is_virtual(&self) -> bool {
-        match self {
-            Self::AF | Self::BC | Self::DE => true,
-            Self::HL | Self::HLi | Self::HLd => true,
-            _ => false,
-        }
+impl Src<u8> for Reg8 {
+    fn read(&self, cpu: &mut CPU) -> u8 {
+        cpu.regs.read8(*self)
     }
 }
 
-impl Src<u8> for Register {
-    unsafe fn read(&self, cpu: &mut CPU) -> u8 {
-        match self {
-            Register::D8 => cpu.pc.d8(),
-            _ => *(cpu.addr(self)),
-        }
+impl Dst<u8> for Reg8 {
+    fn write(&self, cpu: &mut CPU, val: u8) {
+        cpu.regs.write8(*self, val);
     }
 }
 
-impl Src<u8> for Mem<Register> {
-    unsafe fn read(&self, cpu: &mut CPU) -> u8 {
-        let Mem(reg) = self;
-        let addr = *(cpu.vaddr(reg));
-        cpu.read_mem(addr)
+impl Src<u8> for D8 {
+    fn read(&self, cpu: &mut CPU) -> u8 {
+        cpu.fetch_d8()
     }
 }
 
-impl Src<u8> for ZMem<Register> {
-    unsafe fn read(&self, cpu: &mut CPU) -> u8 {
-        let ZMem(reg) = self;
-        let addr = 0xff00
-            + match reg {
-                Register::D8 => cpu.pc.d8(),
-                _ => *(cpu.addr(reg)),
-            } as u16;
-        cpu.read_mem(addr)
+impl Src<u16> for Reg16 {
+    fn read(&self, cpu: &mut CPU) -> u16 {
+        cpu.regs.read16(*self)
     }
 }
 
-impl Dst<u8> for Register {
-    unsafe fn write(&self, cpu: &mut CPU, val: u8) {
-        *(cpu.addr(self)) = val;
+impl Dst<u16> for Reg16 {
+    fn write(&self, cpu: &mut CPU, val: u16) {
+        cpu.regs.write16(*self, val);
     }
 }
 
-impl Dst<u8> for Mem<Register> {
-    unsafe fn write(&self, cpu: &mut CPU, val: u8) {
-        let Mem(reg) = self;
-        let addr = match reg {
-            Register::D16 => cpu.pc.d16(),
-            _ => *(cpu.vaddr(reg)),
-        };
-        cpu.write_mem(addr, val);
+impl Src<u16> for D16 {
+    fn read(&self, cpu: &mut CPU) -> u16 {
+        cpu.fetch_d16()
     }
 }
 
-impl Dst<u8> for ZMem<Register> {
-    unsafe fn write(&self, cpu: &mut CPU, val: u8) {
-        let ZMem(reg) = self;
-        let addr = 0xff00
-            + match reg {
-                Register::D8 => cpu.pc.d8(),
-                _ => *(cpu.addr(reg)),
-            } as u16;
+impl<T: Src<u16>> Src<u8> for Mem<T> {
+    fn read(&self, cpu: &mut CPU) -> u8 {
+        let addr = self.0.read(cpu);
+        cpu.read_mem(addr)
+    }
+}
+
+impl<T: Src<u16>> Dst<u8> for Mem<T> {
+    fn write(&self, cpu: &mut CPU, val: u8) {
+        let addr = self.0.read(cpu);
         cpu.write_mem(addr, val);
     }
 }
 
-impl Src<u16> for Register {
-    unsafe fn read(&self, cpu: &mut CPU) -> u16 {
-        match self {
-            Register::D16 => cpu.pc.d16(),
-            _ => *(cpu.vaddr(self)),
-        }
+impl<T: Src<u16>> Dst<u16> for Mem<T> {
+    fn write(&self, cpu: &mut CPU, val: u16) {
+        let addr = self.0.read(cpu);
+        let l = val as u8;
+        let h = (val >> 8) as u8;
+        cpu.write_mem(addr, l);
+        cpu.write_mem(addr.wrapping_add(1), h);
     }
 }
 
-impl Dst<u16> for Register {
-    unsafe fn write(&self, cpu: &mut CPU, val: u16) {
-        *(cpu.vaddr(self)) = val;
+impl<T: Src<u8>> Src<u8> for ZMem<T> {
+    fn read(&self, cpu: &mut CPU) -> u8 {
+        let addr = 0xff00 + self.0.read(cpu) as u16;
+        cpu.read_mem(addr)
     }
 }
 
-impl Dst<u16> for Mem<Register> {
-    unsafe fn write(&self, cpu: &mut CPU, val: u16) {
-        let Mem(reg) = self;
-        let addr = *(cpu.vaddr(reg));
-        let l = val as u8;
-        let h = (val >> 8) as u8;
-        cpu.write_mem(addr, l);
-        cpu.write_mem(addr + 1, h);
+impl<T: Src<u8>> Dst<u8> for ZMem<T> {
+    fn write(&self, cpu: &mut CPU, val: u8) {
+        let addr = 0xff00 + self.0.read(cpu) as u16;
+        cpu.write_mem(addr, val);
     }
 }
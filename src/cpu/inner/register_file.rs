@@ -0,0 +1,233 @@
+use super::{Flag, Flagd, Reg16, Reg8};
+
+// CPU flag positions
+const ZERO_FLAG_BYTE_POSITION: u8 = 7;
+const SUBTRACT_FLAG_BYTE_POSITION: u8 = 6;
+const HALF_CARRY_FLAG_BYTE_POSITION: u8 = 5;
+const CARRY_FLAG_BYTE_POSITION: u8 = 4;
+
+/// F only has the four flags in its high nibble, the low one always reads back 0.
+const FLAG_MASK: u8 = 0xF0;
+
+/// A, F, B, C, D, E, H and L plus SP and PC. The pairs aren't stored anywhere, they're views
+/// over two of the 8-bit registers with the first one in the high byte.
+#[derive(Clone, Copy, Default)]
+pub struct RegisterFile {
+    pub a: u8,
+    /// Private so nothing can put anything in the low nibble
+    f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl RegisterFile {
+    #[inline]
+    pub fn f(&self) -> u8 {
+        self.f
+    }
+
+    #[inline]
+    pub fn set_f(&mut self, val: u8) {
+        self.f = val & FLAG_MASK;
+    }
+
+    #[inline]
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    /// Sets A and F, dropping F's low nibble. That's how `POP AF` behaves too.
+    #[inline]
+    pub fn set_af(&mut self, val: u16) {
+        let [a, f] = val.to_be_bytes();
+        self.a = a;
+        self.set_f(f);
+    }
+
+    #[inline]
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    #[inline]
+    pub fn set_bc(&mut self, val: u16) {
+        let [b, c] = val.to_be_bytes();
+        self.b = b;
+        self.c = c;
+    }
+
+    #[inline]
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    #[inline]
+    pub fn set_de(&mut self, val: u16) {
+        let [d, e] = val.to_be_bytes();
+        self.d = d;
+        self.e = e;
+    }
+
+    #[inline]
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    #[inline]
+    pub fn set_hl(&mut self, val: u16) {
+        let [h, l] = val.to_be_bytes();
+        self.h = h;
+        self.l = l;
+    }
+
+    #[inline]
+    pub fn read8(&self, r: Reg8) -> u8 {
+        match r {
+            Reg8::A => self.a,
+            Reg8::B => self.b,
+            Reg8::C => self.c,
+            Reg8::D => self.d,
+            Reg8::E => self.e,
+            Reg8::H => self.h,
+            Reg8::L => self.l,
+        }
+    }
+
+    #[inline]
+    pub fn write8(&mut self, r: Reg8, val: u8) {
+        match r {
+            Reg8::A => self.a = val,
+            Reg8::B => self.b = val,
+            Reg8::C => self.c = val,
+            Reg8::D => self.d = val,
+            Reg8::E => self.e = val,
+            Reg8::H => self.h = val,
+            Reg8::L => self.l = val,
+        }
+    }
+
+    #[inline]
+    pub fn read16(&self, r: Reg16) -> u16 {
+        match r {
+            Reg16::AF => self.af(),
+            Reg16::BC => self.bc(),
+            Reg16::DE => self.de(),
+            Reg16::HL => self.hl(),
+            Reg16::SP => self.sp,
+        }
+    }
+
+    #[inline]
+    pub fn write16(&mut self, r: Reg16, val: u16) {
+        match r {
+            Reg16::AF => self.set_af(val),
+            Reg16::BC => self.set_bc(val),
+            Reg16::DE => self.set_de(val),
+            Reg16::HL => self.set_hl(val),
+            Reg16::SP => self.sp = val,
+        }
+    }
+}
+
+impl RegisterFile {
+    #[inline]
+    fn flag(&self, position: u8) -> bool {
+        self.f & (1 << position) != 0
+    }
+
+    #[inline]
+    fn set_flag(&mut self, position: u8, b: bool) {
+        if b {
+            self.f |= 1 << position
+        } else {
+            self.f &= !(1 << position)
+        }
+    }
+}
+
+impl Flagd for RegisterFile {
+    #[inline]
+    fn status(&self, f: Flag) -> bool {
+        match f {
+            Flag::NF => true,
+            Flag::Z => self.flag(ZERO_FLAG_BYTE_POSITION),
+            Flag::NZ => !self.flag(ZERO_FLAG_BYTE_POSITION),
+            Flag::CY => self.flag(CARRY_FLAG_BYTE_POSITION),
+            Flag::NC => !self.flag(CARRY_FLAG_BYTE_POSITION),
+            Flag::HC => self.flag(HALF_CARRY_FLAG_BYTE_POSITION),
+            Flag::S => self.flag(SUBTRACT_FLAG_BYTE_POSITION),
+        }
+    }
+
+    #[inline]
+    fn zero(&mut self, b: bool) {
+        self.set_flag(ZERO_FLAG_BYTE_POSITION, b)
+    }
+
+    #[inline]
+    fn subtract(&mut self, b: bool) {
+        self.set_flag(SUBTRACT_FLAG_BYTE_POSITION, b)
+    }
+
+    #[inline]
+    fn half_carry(&mut self, b: bool) {
+        self.set_flag(HALF_CARRY_FLAG_BYTE_POSITION, b)
+    }
+
+    #[inline]
+    fn carry(&mut self, b: bool) {
+        self.set_flag(CARRY_FLAG_BYTE_POSITION, b)
+    }
+
+    #[inline]
+    fn set_flags(&mut self, zero: bool, subtract: bool, half_carry: bool, carry: bool) {
+        self.zero(zero);
+        self.subtract(subtract);
+        self.half_carry(half_carry);
+        self.carry(carry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_are_views_over_the_halves() {
+        let mut regs = RegisterFile::default();
+        regs.set_bc(0x1234);
+        assert_eq!((regs.b, regs.c), (0x12, 0x34));
+        regs.l = 0xCD;
+        regs.h = 0xAB;
+        assert_eq!(regs.hl(), 0xABCD);
+        regs.write16(Reg16::DE, 0xBEEF);
+        assert_eq!(regs.read8(Reg8::D), 0xBE);
+        assert_eq!(regs.read8(Reg8::E), 0xEF);
+    }
+
+    #[test]
+    fn f_low_nibble_stays_clear() {
+        let mut regs = RegisterFile::default();
+        regs.set_af(0x12FF);
+        assert_eq!(regs.a, 0x12);
+        assert_eq!(regs.af(), 0x12F0);
+        regs.set_f(0x0F);
+        assert_eq!(regs.f(), 0x00);
+    }
+
+    #[test]
+    fn flags_dont_see_each_other() {
+        let mut regs = RegisterFile::default();
+        regs.set_flags(true, true, true, true);
+        assert_eq!(regs.f(), 0xF0);
+        assert!(regs.status(Flag::CY) && regs.status(Flag::Z));
+        regs.carry(false);
+        assert!(regs.status(Flag::NC) && regs.status(Flag::HC));
+    }
+}
@@ -18,9 +18,24 @@ use crate::cpu::CPU;
 /// the stack and control jumps to the starting address of the interrupt.
 ///
 /// If the RESET terminal goes LOW in HALT moode, the mode becomes that of a normal reset.
+///
+/// With IME off and an interrupt already pending, HALT doesn't halt at all. It trips the HALT
+/// bug instead, where PC fails to move past the next opcode and it gets read twice.
+#[inline]
+pub(crate) fn halt(cpu: &mut CPU) -> Timing {
+    if !cpu.interrupts.ime() && cpu.interrupts.pending() != 0 {
+        cpu.halt_bug = true;
+    } else {
+        cpu.halted = true;
+    }
+    Timing::Default
+}
+
+/// The opcodes the LR35902 doesn't have. The CPU hangs on them, interrupts and all, until it's
+/// reset.
 #[inline]
-pub(crate) unsafe fn halt(cpu: &mut CPU) -> Timing {
-    cpu.halted = true;
+pub(crate) fn lock_up(cpu: &mut CPU) -> Timing {
+    cpu.locked = true;
     Timing::Default
 }
 
@@ -44,12 +59,12 @@ pub(crate) unsafe fn halt(cpu: &mut CPU) -> Timing {
 /// instruction following the CALL instruction is pushed to the 2 bytes following the memory byte
 /// specified by the stack pointer SP. The 16-bit immediate operand a16 is then loaded into PC.
 #[inline]
-pub(crate) unsafe fn call(cpu: &mut CPU, f: Flag) -> Timing {
+pub(crate) fn call(cpu: &mut CPU, f: Flag) -> Timing {
     let new_pc = D16.read(cpu);
     if cpu.status(f) {
-        let ret = cpu.pc;
+        let ret = cpu.regs.pc;
         push_u16(cpu, ret);
-        cpu.pc = new_pc;
+        cpu.regs.pc = new_pc;
         Timing::Flag
     } else {
         Timing::Default
@@ -62,10 +77,14 @@ pub(crate) unsafe fn call(cpu: &mut CPU, f: Flag) -> Timing {
 /// incremented by 1 again. (The value of SP is 2 larger than before instruction execution.) The
 /// next instruction is fetched from the address specified by the content of PC (as usual).
 #[inline]
-pub(crate) unsafe fn ret(cpu: &mut CPU, f: Flag) -> Timing {
+pub(crate) fn ret(cpu: &mut CPU, f: Flag) -> Timing {
+    // Checking the condition takes an M-cycle of its own
+    if !matches!(f, Flag::NF) {
+        cpu.m_cycle();
+    }
     if cpu.status(f) {
         let new_pc = pop_u16(cpu);
-        cpu.pc = new_pc;
+        cpu.regs.pc = new_pc;
         Timing::Flag
     } else {
         Timing::Default
@@ -82,8 +101,8 @@ pub(crate) unsafe fn ret(cpu: &mut CPU, f: Flag) -> Timing {
 /// incremented by 1 again. (THe value of SP is 2 larger than before instruction execution.) The
 /// next instruction is fetched from the address specified by the content of PC (as usual).
 #[inline]
-pub(crate) unsafe fn reti(cpu: &mut CPU) -> Timing {
-    ei(cpu);
+pub(crate) fn reti(cpu: &mut CPU) -> Timing {
+    cpu.interrupts.enable();
     ret(cpu, Flag::NF)
 }
 
@@ -100,10 +119,10 @@ pub(crate) unsafe fn reti(cpu: &mut CPU) -> Timing {
 /// page 0 memory, 0x00 is loaded in the higher-order byte of the PC, and 0x30 is loaded in the
 /// lower-order byte.
 #[inline]
-pub(crate) unsafe fn rst(cpu: &mut CPU, n: u8) -> Timing {
-    let pc = cpu.pc;
+pub(crate) fn rst(cpu: &mut CPU, n: u8) -> Timing {
+    let pc = cpu.regs.pc;
     push_u16(cpu, pc);
-    cpu.pc = n as u16;
+    cpu.regs.pc = n as u16;
     Timing::Default
 }
 
@@ -115,7 +134,7 @@ pub(crate) unsafe fn rst(cpu: &mut CPU, n: u8) -> Timing {
 /// If the destination identifies as an address in memory, the cpu will use it's value to
 /// insert to with the value from the source register.
 #[inline]
-pub(crate) unsafe fn ld<T, D: Dst<T>, S: Src<T>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
+pub(crate) fn ld<T, D: Dst<T>, S: Src<T>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let value = src.read(cpu);
     dst.write(cpu, value);
     Timing::Default
@@ -125,12 +144,7 @@ pub(crate) unsafe fn ld<T, D: Dst<T>, S: Src<T>>(cpu: &mut CPU, dst: D, src: S)
 ///
 /// Same behavior as LD, but will increment a 16-bit register
 #[inline]
-pub(crate) unsafe fn ldi<T, D: Dst<T>, S: Src<T>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-    inc: Register,
-) -> Timing {
+pub(crate) fn ldi<T, D: Dst<T>, S: Src<T>>(cpu: &mut CPU, dst: D, src: S, inc: Reg16) -> Timing {
     let t = ld(cpu, dst, src);
     inc_16(cpu, inc);
     t
@@ -140,12 +154,7 @@ pub(crate) unsafe fn ldi<T, D: Dst<T>, S: Src<T>>(
 ///
 /// Same behavior as LD, but will decrement a 16-bit register
 #[inline]
-pub(crate) unsafe fn ldd<T, D: Dst<T>, S: Src<T>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-    dec: Register,
-) -> Timing {
+pub(crate) fn ldd<T, D: Dst<T>, S: Src<T>>(cpu: &mut CPU, dst: D, src: S, dec: Reg16) -> Timing {
     let t = ld(cpu, dst, src);
     dec_16(cpu, dec);
     t
@@ -156,10 +165,10 @@ pub(crate) unsafe fn ldd<T, D: Dst<T>, S: Src<T>>(
 /// flag was specified, the contents of PC are incremented, and the next instruction following the
 /// current JP instruction is executed (as usual).
 #[inline]
-pub(crate) unsafe fn jp<S: Src<u16>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
+pub(crate) fn jp<S: Src<u16>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
     let new_pc = src.read(cpu);
     if cpu.status(f) {
-        cpu.pc = new_pc;
+        cpu.regs.pc = new_pc;
         Timing::Flag
     } else {
         Timing::Default
@@ -169,12 +178,12 @@ pub(crate) unsafe fn jp<S: Src<u16>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
 /// If the flagged condition is met, jump s8 steps from the current address stored in the program
 /// counter (PC). If not, the instruction following the current JP instruction is executed (as usual).
 #[inline]
-pub(crate) unsafe fn jr<S: Src<u8>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
+pub(crate) fn jr<S: Src<u8>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
     let offset = (src.read(cpu) as i8) as i16;
     if cpu.status(f) {
-        let pc = cpu.pc as i16;
+        let pc = cpu.regs.pc as i16;
         let new_pc = (pc + offset) as u16;
-        cpu.pc = new_pc;
+        cpu.regs.pc = new_pc;
         Timing::Flag
     } else {
         Timing::Default
@@ -184,10 +193,10 @@ pub(crate) unsafe fn jr<S: Src<u8>>(cpu: &mut CPU, f: Flag, src: S) -> Timing {
 /// Take the logical AND for each bit of the contents of the source register and the contents
 /// of register A, and store the results in register A.
 #[inline]
-pub(crate) unsafe fn and<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
+pub(crate) fn and<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
     let a = src.read(cpu);
-    let r = a & cpu.a;
-    cpu.a = r;
+    let r = a & cpu.regs.a;
+    cpu.regs.a = r;
     cpu.set_flags(r == 0, false, true, false);
     Timing::Default
 }
@@ -195,11 +204,7 @@ pub(crate) unsafe fn and<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
 /// Subtract the contents of the source register and the CY flag from the contents of register A,
 /// and store the results in register A.
 #[inline]
-pub(crate) unsafe fn sbc<D: Dst<u8> + Src<u8>, S: Src<u8>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-) -> Timing {
+pub(crate) fn sbc<D: Dst<u8> + Src<u8>, S: Src<u8>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let a = dst.read(cpu) as i16;
     let b = src.read(cpu) as i16;
     let c = if cpu.status(Flag::CY) { 1 } else { 0 };
@@ -221,11 +226,7 @@ pub(crate) unsafe fn sbc<D: Dst<u8> + Src<u8>, S: Src<u8>>(
 /// store the results in the 8-bit accumulator. If the source is a virtual register, it will use
 /// the value at the address given by that register.
 #[inline]
-pub(crate) unsafe fn adc<D: Dst<u8> + Src<u8>, S: Src<u8>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-) -> Timing {
+pub(crate) fn adc<D: Dst<u8> + Src<u8>, S: Src<u8>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let a = dst.read(cpu) as u16;
     let b = src.read(cpu) as u16;
     let c = if cpu.status(Flag::CY) { 1 } else { 0 };
@@ -241,26 +242,24 @@ pub(crate) unsafe fn adc<D: Dst<u8> + Src<u8>, S: Src<u8>>(
 }
 
 #[inline]
-pub(crate) unsafe fn add_sp(cpu: &mut CPU) -> Timing {
+pub(crate) fn add_sp(cpu: &mut CPU) -> Timing {
     let new_sp = offset_sp(cpu);
-    cpu.sp = new_sp;
+    cpu.regs.sp = new_sp;
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn ld_hl_sp(cpu: &mut CPU) -> Timing {
+pub(crate) fn ld_hl_sp(cpu: &mut CPU) -> Timing {
     let sp = offset_sp(cpu);
-    cpu.h = (sp >> 8) as u8;
-    cpu.l = sp as u8;
+    cpu.regs.set_hl(sp);
     Timing::Default
 }
 
 #[inline]
-#[doc(ignore)]
-pub(crate) unsafe fn offset_sp(cpu: &mut CPU) -> u16 {
+pub(crate) fn offset_sp(cpu: &mut CPU) -> u16 {
     let o: u8 = D8.read(cpu); // TODO this may be bugged, needs to be i8
     let offset = o as i32;
-    let sp = (cpu.sp as i16) as i32;
+    let sp = (cpu.regs.sp as i16) as i32;
     let r = sp + offset;
     cpu.set_flags(
         false,
@@ -272,11 +271,7 @@ pub(crate) unsafe fn offset_sp(cpu: &mut CPU) -> u16 {
 }
 
 #[inline]
-pub(crate) unsafe fn add_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-) -> Timing {
+pub(crate) fn add_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let a = dst.read(cpu) as u16;
     let b = src.read(cpu) as u16;
     let r = a + b;
@@ -287,11 +282,7 @@ pub(crate) unsafe fn add_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(
 }
 
 #[inline]
-pub(crate) unsafe fn add_16<D: Dst<u16> + Src<u16>, S: Src<u16>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-) -> Timing {
+pub(crate) fn add_16<D: Dst<u16> + Src<u16>, S: Src<u16>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let a = dst.read(cpu) as u32;
     let b = src.read(cpu) as u32;
     let r = a + b;
@@ -303,11 +294,7 @@ pub(crate) unsafe fn add_16<D: Dst<u16> + Src<u16>, S: Src<u16>>(
 }
 
 #[inline]
-pub(crate) unsafe fn sub_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(
-    cpu: &mut CPU,
-    dst: D,
-    src: S,
-) -> Timing {
+pub(crate) fn sub_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(cpu: &mut CPU, dst: D, src: S) -> Timing {
     let a = dst.read(cpu) as u16;
     let b = src.read(cpu) as u16;
     let r = a.wrapping_sub(b);
@@ -318,35 +305,35 @@ pub(crate) unsafe fn sub_8<D: Dst<u8> + Src<u8>, S: Src<u8>>(
 }
 
 #[inline]
-pub(crate) unsafe fn rrca(cpu: &mut CPU) -> Timing {
+pub(crate) fn rrca(cpu: &mut CPU) -> Timing {
     rrc(cpu, A);
     cpu.zero(false);
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn rla(cpu: &mut CPU) -> Timing {
+pub(crate) fn rla(cpu: &mut CPU) -> Timing {
     rl(cpu, A);
     cpu.zero(false);
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn rra(cpu: &mut CPU) -> Timing {
+pub(crate) fn rra(cpu: &mut CPU) -> Timing {
     rr(cpu, A);
     cpu.zero(false);
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn rlca(cpu: &mut CPU) -> Timing {
+pub(crate) fn rlca(cpu: &mut CPU) -> Timing {
     rlc(cpu, A);
     cpu.zero(false);
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn rlc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn rlc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a.rotate_left(1);
     loc.write(cpu, r);
@@ -354,7 +341,7 @@ pub(crate) unsafe fn rlc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn rl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn rl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a << 1;
     let r = if cpu.status(Flag::CY) { r | 0x01 } else { r };
@@ -363,7 +350,7 @@ pub(crate) unsafe fn rl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn rr<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn rr<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a >> 1;
     let r = if cpu.status(Flag::CY) { r | 0x80 } else { r };
@@ -372,7 +359,7 @@ pub(crate) unsafe fn rr<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn rrc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn rrc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a.rotate_right(1);
     loc.write(cpu, r);
@@ -380,7 +367,7 @@ pub(crate) unsafe fn rrc<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn sla<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn sla<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a << 1;
     loc.write(cpu, r);
@@ -388,7 +375,7 @@ pub(crate) unsafe fn sla<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn sra<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn sra<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a >> 1;
     let r = (a & 0x80) | r;
@@ -398,8 +385,8 @@ pub(crate) unsafe fn sra<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 
 /// Adjust the accumulator to a binary-coded decimal (BCD) number after BCD addition and subtraction operations.
 #[inline]
-pub(crate) unsafe fn daa(cpu: &mut CPU) -> Timing {
-    let mut a = cpu.a as u16;
+pub(crate) fn daa(cpu: &mut CPU) -> Timing {
+    let mut a = cpu.regs.a as u16;
     let n = cpu.status(S);
     let c = cpu.status(CY);
     let h = cpu.status(HC);
@@ -413,23 +400,23 @@ pub(crate) unsafe fn daa(cpu: &mut CPU) -> Timing {
         }
     } else {
         if c || ((a & 0xff) > 0x99) {
-            a = a + 0x60;
+            a += 0x60;
             cpu.carry(true)
         }
         if h || ((a & 0x0f) > 0x09) {
-            a = a + 0x06
+            a += 0x06
         }
     }
     cpu.zero((a as u8) == 0);
     cpu.half_carry(false);
 
-    cpu.a = a as u8;
+    cpu.regs.a = a as u8;
 
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn scf(cpu: &mut CPU) -> Timing {
+pub(crate) fn scf(cpu: &mut CPU) -> Timing {
     cpu.subtract(false);
     cpu.half_carry(false);
     cpu.carry(true);
@@ -437,7 +424,7 @@ pub(crate) unsafe fn scf(cpu: &mut CPU) -> Timing {
 }
 
 #[inline]
-pub(crate) unsafe fn ccf(cpu: &mut CPU) -> Timing {
+pub(crate) fn ccf(cpu: &mut CPU) -> Timing {
     cpu.subtract(false);
     cpu.half_carry(false);
     cpu.carry(!cpu.status(Flag::CY));
@@ -445,7 +432,7 @@ pub(crate) unsafe fn ccf(cpu: &mut CPU) -> Timing {
 }
 
 #[inline]
-pub(crate) unsafe fn bit<S: Src<u8>>(cpu: &mut CPU, bit: u8, src: S) {
+pub(crate) fn bit<S: Src<u8>>(cpu: &mut CPU, bit: u8, src: S) {
     let a = src.read(cpu) >> bit;
     cpu.zero((a & 0x01) == 0);
     cpu.subtract(false);
@@ -453,7 +440,7 @@ pub(crate) unsafe fn bit<S: Src<u8>>(cpu: &mut CPU, bit: u8, src: S) {
 }
 
 #[inline]
-pub(crate) unsafe fn srl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn srl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
     let r = a >> 1;
     loc.write(cpu, r);
@@ -461,50 +448,50 @@ pub(crate) unsafe fn srl<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
 }
 
 #[inline]
-pub(crate) unsafe fn res<L: Src<u8> + Dst<u8>>(cpu: &mut CPU, bit: u8, loc: L) {
+pub(crate) fn res<L: Src<u8> + Dst<u8>>(cpu: &mut CPU, bit: u8, loc: L) {
     let a = loc.read(cpu);
     let r = a & !(0x01 << bit);
     loc.write(cpu, r)
 }
 
 #[inline]
-pub(crate) unsafe fn set<L: Src<u8> + Dst<u8>>(cpu: &mut CPU, bit: u8, loc: L) {
+pub(crate) fn set<L: Src<u8> + Dst<u8>>(cpu: &mut CPU, bit: u8, loc: L) {
     let a = loc.read(cpu);
     let r = a | (0x01 << bit);
     loc.write(cpu, r)
 }
 
 #[inline]
-pub(crate) unsafe fn swap_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
+pub(crate) fn swap_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) {
     let a = loc.read(cpu);
-    let r = (a << 4) | (a >> 4);
+    let r = a.rotate_left(4);
     loc.write(cpu, r);
     cpu.set_flags(r == 0, false, false, false);
 }
 
 #[inline]
-pub(crate) unsafe fn xor<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
+pub(crate) fn xor<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
     let a = src.read(cpu);
-    let r = cpu.a ^ a;
-    cpu.a = r;
+    let r = cpu.regs.a ^ a;
+    cpu.regs.a = r;
     cpu.set_flags(r == 0, false, false, false);
     Timing::Default
 }
 
 #[inline]
-pub(crate) unsafe fn or<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
+pub(crate) fn or<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
     let a = src.read(cpu);
-    let r = cpu.a | a;
-    cpu.a = r;
+    let r = cpu.regs.a | a;
+    cpu.regs.a = r;
     cpu.set_flags(r == 0, false, false, false);
     Timing::Default
 }
 
 /// Take the one's complement (i.e., flip all bits) of the contents of register A.
 #[inline]
-pub(crate) unsafe fn cpl(cpu: &mut CPU) -> Timing {
-    let a = cpu.a;
-    cpu.a = !a;
+pub(crate) fn cpl(cpu: &mut CPU) -> Timing {
+    let a = cpu.regs.a;
+    cpu.regs.a = !a;
     cpu.subtract(true);
     cpu.half_carry(true);
     Timing::Default
@@ -515,8 +502,8 @@ pub(crate) unsafe fn cpl(cpu: &mut CPU) -> Timing {
 ///
 /// The execution of this instruction does not affect the contents of register A.
 #[inline]
-pub(crate) unsafe fn cp<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
-    let a = cpu.a;
+pub(crate) fn cp<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
+    let a = cpu.regs.a;
     let value = src.read(cpu);
     cpu.set_flags(
         a == value,
@@ -529,7 +516,7 @@ pub(crate) unsafe fn cp<S: Src<u8>>(cpu: &mut CPU, src: S) -> Timing {
 
 /// Increments the contents of `loc` by one.
 #[inline]
-pub(crate) unsafe fn inc_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timing {
+pub(crate) fn inc_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timing {
     let value = loc.read(cpu);
     let result = value.wrapping_add(1);
     loc.write(cpu, result);
@@ -541,7 +528,7 @@ pub(crate) unsafe fn inc_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timin
 
 /// Increments the contents of `loc` by one.
 #[inline]
-pub(crate) unsafe fn inc_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Timing {
+pub(crate) fn inc_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Timing {
     // No condition bits are affected for 16 bit inc
     let value = loc.read(cpu);
     loc.write(cpu, value.wrapping_add(1));
@@ -550,7 +537,7 @@ pub(crate) unsafe fn inc_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Ti
 
 /// Decrements the contents of `loc` by one.
 #[inline]
-pub(crate) unsafe fn dec_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timing {
+pub(crate) fn dec_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timing {
     let value = loc.read(cpu);
     let result = value.wrapping_sub(1);
     loc.write(cpu, result);
@@ -562,7 +549,7 @@ pub(crate) unsafe fn dec_8<L: Dst<u8> + Src<u8>>(cpu: &mut CPU, loc: L) -> Timin
 
 /// Decrements the contents of `loc` by one.
 #[inline]
-pub(crate) unsafe fn dec_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Timing {
+pub(crate) fn dec_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Timing {
     // No condition bits are affected for 16 bit dec
     let value = loc.read(cpu);
     loc.write(cpu, value.wrapping_sub(1));
@@ -574,7 +561,7 @@ pub(crate) unsafe fn dec_16<L: Dst<u16> + Src<u16>>(cpu: &mut CPU, loc: L) -> Ti
 /// Subtract 1 from the stack pointer SP, and put the contents of the higher portion of register pair BC on the stack.
 /// Subtract 2 from SP, and put the lower portion of register pair BC on the stack.
 #[inline]
-pub(crate) unsafe fn push<S: Src<u16>>(cpu: &mut CPU, src: S) -> Timing {
+pub(crate) fn push<S: Src<u16>>(cpu: &mut CPU, src: S) -> Timing {
     let value = src.read(cpu);
     push_u16(cpu, value);
     Timing::Default
@@ -585,7 +572,7 @@ pub(crate) unsafe fn push<S: Src<u16>>(cpu: &mut CPU, src: S) -> Timing {
 /// Load the contents of memory specified by stack pointer SP into the lower portion of BC.
 /// Add 1 to SP and load the contents from the new memory location into the upper portion of BC.
 #[inline]
-pub(crate) unsafe fn pop<D: Dst<u16>>(cpu: &mut CPU, dst: D) -> Timing {
+pub(crate) fn pop<D: Dst<u16>>(cpu: &mut CPU, dst: D) -> Timing {
     let value = pop_u16(cpu);
     dst.write(cpu, value);
     Timing::Default
@@ -596,8 +583,8 @@ pub(crate) unsafe fn pop<D: Dst<u16>>(cpu: &mut CPU, dst: D) -> Timing {
 /// Even if a DI instruction is executed in an interrupt routine, the IME flag is set if a return
 /// is performed with a RETI instruction.
 #[inline]
-pub(crate) unsafe fn di(cpu: &mut CPU) -> Timing {
-    cpu.ime = false;
+pub(crate) fn di(cpu: &mut CPU) -> Timing {
+    cpu.interrupts.disable();
     Timing::Default
 }
 
@@ -607,51 +594,64 @@ pub(crate) unsafe fn di(cpu: &mut CPU) -> Timing {
 /// The IME flag is reset immediately after an interrupt occurs. The IME flag reset remains in
 /// effect if coontrol is returned from the interrupt routine by a RET instruction. However, if an
 /// EI instruction is executed in the interrupt routine, control is returned with IME = 1.
+///
+/// IME doesn't actually come on until the instruction after EI is done.
 #[inline]
-pub(crate) unsafe fn ei(cpu: &mut CPU) -> Timing {
-    cpu.ime = true;
+pub(crate) fn ei(cpu: &mut CPU) -> Timing {
+    cpu.interrupts.enable_delayed();
     Timing::Default
 }
 
 /// Push 1 byte onto the memory stack
 #[inline]
-pub(crate) unsafe fn push_u8(cpu: &mut CPU, value: u8) {
-    let sp = cpu.sp.wrapping_sub(1);
+pub(crate) fn push_u8(cpu: &mut CPU, value: u8) {
+    let sp = cpu.regs.sp.wrapping_sub(1);
     cpu.write_mem(sp, value);
-    cpu.sp = sp
+    cpu.regs.sp = sp
 }
 
-/// Push 2 bytes onto the memory stack
+/// Push 2 bytes onto the memory stack, after the M-cycle it takes to decrement SP
 #[inline]
-pub(crate) unsafe fn push_u16(cpu: &mut CPU, value: u16) {
+pub(crate) fn push_u16(cpu: &mut CPU, value: u16) {
+    cpu.m_cycle();
     push_u8(cpu, (value >> 8) as u8);
     push_u8(cpu, value as u8);
 }
 
 /// Pop 1 byte from the memory stack
 #[inline]
-pub(crate) unsafe fn pop_u8(cpu: &mut CPU) -> u8 {
-    let sp = cpu.sp;
+pub(crate) fn pop_u8(cpu: &mut CPU) -> u8 {
+    let sp = cpu.regs.sp;
     let value = cpu.read_mem(sp);
-    cpu.sp = sp.wrapping_add(1);
+    cpu.regs.sp = sp.wrapping_add(1);
     value
 }
 
 /// Pop 2 bytes from the memory stack
 #[inline]
-pub(crate) unsafe fn pop_u16(cpu: &mut CPU) -> u16 {
-    let low = cpu.pc.d8() as u16;
-    let high = cpu.pc.d8() as u16;
+pub(crate) fn pop_u16(cpu: &mut CPU) -> u16 {
+    let low = pop_u8(cpu) as u16;
+    let high = pop_u8(cpu) as u16;
     (high << 8) | low
 }
 
-#[inline]
-pub(crate) unsafe fn stop() -> Timing {
-    // http://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
-    //
-    // Instruction STOP has according to manuals opcode 10 00 and
-    // thus is 2 bytes long. Anyhow it seems there is no reason for
-    // it so some assemblers code it simply as one byte instruction 10
-    //
+/// STOP is two bytes long, the second being padding that assemblers don't always bother with.
+///
+/// With a speed switch armed through KEY1 it switches speed instead of stopping, which resets
+/// DIV and stops the CPU and timer for a while as the clock settles. Otherwise everything
+/// stops, DIV included, until a button selected in P1 is pressed. If one already is, it
+/// doesn't stop at all.
+#[inline]
+pub(crate) fn stop(cpu: &mut CPU) -> Timing {
+    cpu.fetch_d8();
+    cpu.timer.write(0xff04, 0);
+
+    if cpu.cgb && cpu.speed_switch_armed {
+        cpu.speed_switch_armed = false;
+        cpu.double_speed = !cpu.double_speed;
+        cpu.pause += SPEED_SWITCH_CYCLES;
+    } else if !cpu.joypad.pressed() {
+        cpu.stopped = true;
+    }
     Timing::Default
 }
@@ -0,0 +1,192 @@
+// IE, IF and IME, and which interrupt gets taken when
+
+/// Handler addresses, in priority order: VBlank, STAT, timer, serial and joypad.
+const VECTORS: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+/// IF only has five bits, the rest read back as 1.
+const IF_UNUSED: u8 = 0xE0;
+
+/// T-cycles from taking an interrupt to the handler's first fetch: two idle M-cycles, two
+/// pushing PC and one jumping.
+pub const DISPATCH_CYCLES: u32 = 20;
+
+/// The interrupt controller, IF at 0xFF0F and IE at 0xFFFF along with IME.
+pub struct Interrupts {
+    /// IE, all eight bits of it even though only five do anything
+    enable: u8,
+    /// IF, low five bits
+    flags: u8,
+    ime: bool,
+    /// Instructions left to finish before EI turns IME on
+    ei_delay: u8,
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self {
+            enable: 0,
+            flags: 0,
+            ime: false,
+            ei_delay: 0,
+        }
+    }
+
+    /// Raises `bits` in IF.
+    #[inline]
+    pub fn request(&mut self, bits: u8) {
+        self.flags |= bits & !IF_UNUSED;
+    }
+
+    #[inline]
+    pub fn read_if(&self) -> u8 {
+        IF_UNUSED | self.flags
+    }
+
+    #[inline]
+    pub fn write_if(&mut self, val: u8) {
+        self.flags = val & !IF_UNUSED;
+    }
+
+    #[inline]
+    pub fn read_ie(&self) -> u8 {
+        self.enable
+    }
+
+    #[inline]
+    pub fn write_ie(&mut self, val: u8) {
+        self.enable = val;
+    }
+
+    /// Interrupts that are both requested and enabled, whatever IME says.
+    #[inline]
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flags & !IF_UNUSED
+    }
+
+    #[inline]
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    /// EI, which only turns IME on once the instruction after it is done. That's what lets
+    /// `EI / RET` return before the next interrupt comes in.
+    #[inline]
+    pub fn enable_delayed(&mut self) {
+        if !self.ime && self.ei_delay == 0 {
+            self.ei_delay = 2;
+        }
+    }
+
+    /// RETI, which turns IME straight back on.
+    #[inline]
+    pub fn enable(&mut self) {
+        self.ime = true;
+        self.ei_delay = 0;
+    }
+
+    /// DI, which also cancels an EI that hasn't kicked in yet.
+    #[inline]
+    pub fn disable(&mut self) {
+        self.ime = false;
+        self.ei_delay = 0;
+    }
+
+    /// Call after every instruction, it's where EI's delay runs out.
+    #[inline]
+    pub fn instruction_done(&mut self) {
+        if self.ei_delay > 0 {
+            self.ei_delay -= 1;
+            if self.ei_delay == 0 {
+                self.ime = true;
+            }
+        }
+    }
+
+    /// Whether an interrupt gets taken before the next instruction.
+    #[inline]
+    pub fn should_dispatch(&self) -> bool {
+        self.ime && self.pending() != 0
+    }
+
+    /// Settles which interrupt is being taken, clearing its IF bit and nothing else, and returns
+    /// its vector.
+    ///
+    /// This happens after PC's high byte has been pushed, so if that push landed on IE and
+    /// disabled everything that was pending, there's nothing left to take. The CPU jumps to
+    /// 0x0000 instead and IF is left alone.
+    #[inline]
+    pub fn acknowledge(&mut self) -> u16 {
+        let pending = self.pending();
+        if pending == 0 {
+            return 0x0000;
+        }
+        let int = pending.trailing_zeros() as usize;
+        self.flags &= !(1 << int);
+        VECTORS[int]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_serviced_bit_clears() {
+        let mut ints = Interrupts::new();
+        ints.write_ie(0x1F);
+        ints.request(0b0001_0110);
+        assert_eq!(ints.acknowledge(), 0x48);
+        assert_eq!(ints.read_if(), 0xE0 | 0b0001_0100);
+        assert_eq!(ints.acknowledge(), 0x50);
+        assert_eq!(ints.acknowledge(), 0x60);
+        assert_eq!(ints.read_if(), 0xE0);
+    }
+
+    #[test]
+    fn unused_if_bits_read_as_ones() {
+        let mut ints = Interrupts::new();
+        assert_eq!(ints.read_if(), 0xE0);
+        ints.write_if(0xFF);
+        assert_eq!(ints.read_if(), 0xFF);
+        ints.write_if(0x00);
+        assert_eq!(ints.read_if(), 0xE0);
+
+        ints.write_ie(0xFF);
+        assert_eq!(ints.read_ie(), 0xFF);
+        ints.request(0xFF);
+        assert_eq!(ints.pending(), 0x1F);
+    }
+
+    #[test]
+    fn ei_waits_an_instruction() {
+        let mut ints = Interrupts::new();
+        ints.write_ie(0x01);
+        ints.request(0x01);
+
+        ints.enable_delayed();
+        ints.instruction_done();
+        assert!(!ints.should_dispatch());
+        ints.instruction_done();
+        assert!(ints.should_dispatch());
+    }
+
+    #[test]
+    fn di_cancels_a_pending_ei() {
+        let mut ints = Interrupts::new();
+        ints.enable_delayed();
+        ints.instruction_done();
+        ints.disable();
+        ints.instruction_done();
+        assert!(!ints.ime());
+    }
+
+    #[test]
+    fn nothing_left_jumps_to_zero() {
+        let mut ints = Interrupts::new();
+        ints.write_ie(0x04);
+        ints.request(0x04);
+        ints.write_ie(0x00);
+        assert_eq!(ints.acknowledge(), 0x0000);
+        assert_eq!(ints.read_if(), 0xE4);
+    }
+}
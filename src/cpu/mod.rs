@@ -1,224 +1,554 @@
 // LR35902 ulator
 
+mod error;
 mod inner;
 mod instructions;
+mod interrupts;
 mod opcode;
 #[cfg(test)]
 mod tests;
 
 use inner::Flag::*;
-use inner::Register::*;
+use inner::Reg16::*;
+use inner::Reg8::*;
 use inner::*;
+use interrupts::{Interrupts, DISPATCH_CYCLES};
+
+pub use error::EmuError;
+
+use crate::apu::{Apu, AudioSink, CaptureSink, Layout, Mixer, Scope};
+use crate::dma::{Hdma, OamDma, OamDmaMode, HDMA_BLOCK_SIZE, OAM_DMA_LENGTH};
+use crate::infrared::{Infrared, IrTransport};
+use crate::io::input::InputSource;
+use crate::io::joypad::Joypad;
+use crate::ppu::{Ppu, SCREEN_WIDTH};
+use crate::serial::{LinkTransport, Serial};
+use crate::timer::Timer;
 
 // Our opcode time tables
 use opcode::*;
 
-// CPU flag positions
-const ZERO_FLAG_BYTE_POSITION: u8 = 7;
-const SUBTRACT_FLAG_BYTE_POSITION: u8 = 6;
-const HALF_CARRY_FLAG_BYTE_POSITION: u8 = 5;
-const CARRY_FLAG_BYTE_POSITION: u8 = 4;
-
-const BUS_SIZE: usize = 65535;
-const RAM_SIZE: usize = 1024 * 32;
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = RAM_BANK_SIZE * 8;
 const VRAM_SIZE: usize = 0x7F;
 
+/// CPU T-cycles a speed switch keeps the CPU and timer stopped for, 2050 M-cycles.
+const SPEED_SWITCH_CYCLES: u32 = 2050 * 4;
+/// What `execute` reports passing for each M-cycle HALT idles.
+const HALT_CYCLES: u32 = 4;
+/// What `execute` reports passing while STOP has everything stopped.
+const STOPPED_CYCLES: u32 = 4;
+/// What `execute` reports passing for each M-cycle the CPU spends locked up.
+const LOCKED_CYCLES: u32 = 4;
+
+/// How closely `execute` lines up the rest of the system with what the CPU's doing.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Runs each instruction in one go, then catches everything else up by however long the
+    /// opcode tables say it took. Every access lands at the start of the instruction.
+    Fast,
+    /// Catches everything else up by an M-cycle at every memory access and internal delay, so
+    /// reads and writes happen when they would on hardware, part way through the instruction.
+    Accurate,
+}
+
 // https://github.com/nekronos/gbc_rs/blob/master/src/gbc/interconnect.rs
 
-#[repr(C)]
-// The order of these fields does matter, and tbh this may not work on any other chip or compiler
 pub struct CPU {
-    /// CPU flag register, see inner/flag_register.rs
-    flag: u8,
-    /// Accumulator register
-    a: u8,
-    /// Registers
-    c: u8,
-    b: u8,
-    e: u8,
-    d: u8,
-    l: u8,
-    h: u8,
+    /// A through L, SP and PC, see inner/register_file.rs
+    regs: RegisterFile,
     halted: bool,
-    /// Program counter
-    pc: u16,
-    /// Stack pointer, see inner/program_counter.rs
-    sp: u16,
+    /// HALT fell through with an interrupt already pending and IME off, so the next fetch
+    /// doesn't move PC
+    halt_bug: bool,
+    /// WRAM bank selected for 0xD000-0xDFFF, CGB only
     svbk: u8,
     ppu_dma: u8,
-    ime: bool,
-    int_enable: u8,
-    int_flags: u8,
+    interrupts: Interrupts,
+    /// Offset into `ram` of the bank mapped at 0xD000
     ram_offset: usize,
-    /// Memory bus, see inner/memory_bus.rs
-    bus: [u8; 65535],
+    /// Whether we're running a CGB or a DMG
+    cgb: bool,
+    /// CGB double speed mode, where the CPU runs twice as fast as everything else
+    double_speed: bool,
+    /// KEY1 bit 0, the next STOP switches speed
+    speed_switch_armed: bool,
+    /// STOP's low power mode, where nothing runs until a button is pressed
+    stopped: bool,
+    /// Hung by an illegal opcode, which nothing short of a reset gets out of
+    locked: bool,
+    /// CPU T-cycles the CPU and timer sit out while a speed switch settles
+    pause: u32,
+    /// T-cycles the CPU sits out while something else (HDMA) owns the bus
+    stall: u32,
+    mode: ExecutionMode,
+    /// CPU T-cycles already clocked part way through the current instruction, accurate mode only
+    ticked: u32,
+    ppu: Ppu,
+    apu: Apu,
+    mixer: Mixer,
+    oam_dma: OamDma,
+    hdma: Hdma,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    ir: Infrared,
+    /// Cartridge ROM, see `load_rom`
+    rom: &'static [u8],
+    /// ROM bank mapped at 0x4000-0x7FFF
+    rom_bank: usize,
     ram: [u8; RAM_SIZE],
     vram: [u8; VRAM_SIZE],
 }
 
 impl CPU {
-    pub unsafe fn new() -> Self {
+    /// A CGB, or a DMG when `cgb` is false. The registers start where that model's boot ROM
+    /// leaves them, which is how games tell the two apart.
+    pub fn new(cgb: bool) -> Self {
         Self {
-            a: 0x11,
-            flag: 0x00,
-            b: 0x00,
-            c: 0x00,
-            d: 0xFF,
-            e: 0x56,
-            h: 0x00,
-            l: 0x0D,
+            regs: {
+                // Whatever the boot ROM leaves behind, it hands over at 0x0100 either way
+                let mut regs = RegisterFile::default();
+                regs.sp = 0xFFFE;
+                regs.pc = 0x0100;
+                if cgb {
+                    regs.set_af(0x1180);
+                    regs.set_de(0xFF56);
+                    regs.set_hl(0x000D);
+                } else {
+                    regs.set_af(0x01B0);
+                    regs.set_bc(0x0013);
+                    regs.set_de(0x00D8);
+                    regs.set_hl(0x014D);
+                }
+                regs
+            },
             halted: false,
-            pc: 0,
-            sp: 0,
+            halt_bug: false,
             svbk: 0,
             ppu_dma: 0,
-            ime: false,
-            int_enable: 0,
-            int_flags: 0,
-            ram_offset: 0,
-            bus: [0u8; BUS_SIZE],
+            interrupts: Interrupts::new(),
+            ram_offset: RAM_BANK_SIZE,
+            cgb,
+            double_speed: false,
+            speed_switch_armed: false,
+            stopped: false,
+            locked: false,
+            pause: 0,
+            stall: 0,
+            mode: ExecutionMode::Fast,
+            ticked: 0,
+            ppu: Ppu::new(cgb),
+            apu: Apu::new(cgb),
+            mixer: Mixer::new(22050, Layout::Mono),
+            oam_dma: OamDma::new(OamDmaMode::Accurate),
+            hdma: Hdma::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(cgb),
+            ir: Infrared::new(cgb),
+            rom: &[],
+            rom_bank: 1,
             ram: [0u8; RAM_SIZE],
             vram: [0u8; VRAM_SIZE],
         }
     }
 
+    /// Maps `rom` into 0x0000-0x7FFF and skips the boot ROM, leaving PC and SP where it would.
+    ///
+    /// Bank switching only goes as far as MBC1's ROM bank register, which is all test ROMs need.
+    pub fn load_rom(&mut self, rom: &'static [u8]) {
+        self.rom = rom;
+        self.rom_bank = 1;
+        self.regs.pc = 0x0100;
+        self.regs.sp = 0xFFFE;
+    }
+
     #[inline]
-    unsafe fn step(&mut self) -> (bool, u8) {
-        let tmp = self.bus.read_byte(self.pc);
+    pub fn pc(&self) -> u16 {
+        self.regs.pc
+    }
+
+    /// Returns A, F, B, C, D, E, H and L, in that order.
+    #[inline]
+    pub fn registers(&self) -> [u8; 8] {
+        let r = &self.regs;
+        [r.a, r.f(), r.b, r.c, r.d, r.e, r.h, r.l]
+    }
+
+    /// Reads `addr` like the CPU would, which during OAM DMA means mostly seeing the DMA, without
+    /// anything else happening.
+    #[inline]
+    pub fn peek(&self, addr: u16) -> u8 {
+        if self.oam_dma.blocks(addr) {
+            return self.oam_dma.current();
+        }
+        self.read_bus(addr)
+    }
+
+    #[inline]
+    fn step(&mut self) -> (bool, u8) {
+        let tmp = self.fetch_d8();
+        if core::mem::take(&mut self.halt_bug) {
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
         if tmp == 0xCB {
-            (true, self.bus.read_byte(self.pc + 1))
+            (true, self.fetch_d8())
         } else {
             (false, tmp)
         }
     }
 
+    /// Reads the byte at PC and moves PC past it.
+    #[inline]
+    fn fetch_d8(&mut self) -> u8 {
+        let val = self.read_mem(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        val
+    }
+
+    /// Reads the little endian word at PC and moves PC past it.
+    #[inline]
+    fn fetch_d16(&mut self) -> u16 {
+        let low = self.fetch_d8() as u16;
+        let high = self.fetch_d8() as u16;
+        (high << 8) | low
+    }
+
     /*
        0xFF00-0xFF7F: Port/Mode registers, control register, sound register
        0xFF80-0xFFFE: Working & Stack RAM (127 bytes)
        0xFFFF: Interrupt Enable Register
     */
 
+    /// The CPU reading `addr`, which takes an M-cycle.
     #[inline]
-    unsafe fn read_mem(&self, addr: u16) -> u8 {
+    fn read_mem(&mut self, addr: u16) -> u8 {
+        self.m_cycle();
+        self.peek(addr)
+    }
+
+    #[inline]
+    fn write_mem(&mut self, addr: u16, val: u8) {
+        self.m_cycle();
+        if self.oam_dma.blocks(addr) {
+            return;
+        }
+        self.write_bus(addr, val)
+    }
+
+    #[inline]
+    fn read_bus(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7fff => unimplemented!("{}", addr),
-            0x8000..=0x9fff => unimplemented!("{}", addr),
-            0xa000..=0xbfff => unimplemented!("{}", addr),
-            0xc000..=0xcfff => unimplemented!("{}", addr),
-            0xd000..=0xdfff => unimplemented!("{}", addr),
-            0xe000..=0xfdff => self.read_mem(addr - 0xE000 + 0xC000),
-
-            0xff00 => unimplemented!("{}", addr),
-
-            0xff01..=0xff02 => {
-                // serial IO
-                unimplemented!("{}", addr)
+            0x0000..=0x3fff => self.rom.get(addr as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7fff => {
+                let offset = self.rom_bank * ROM_BANK_SIZE + (addr - 0x4000) as usize;
+                self.rom.get(offset).copied().unwrap_or(0xFF)
             }
-            0xff04..=0xff07 => unimplemented!("{}", addr),
+            0x8000..=0x9fff => self.ppu.read(addr),
+            0xc000..=0xcfff => self.ram[(addr - 0xC000) as usize],
+            0xd000..=0xdfff => self.ram[(addr - 0xD000) as usize + self.ram_offset],
+            0xe000..=0xfdff => self.read_bus(addr - 0xE000 + 0xC000),
+
+            0xff00 => self.joypad.read(),
+
+            0xff01..=0xff02 => self.serial.read(addr),
+            0xff04..=0xff07 => self.timer.read(addr),
 
-            0xff10..=0xff3f => unimplemented!("{}", addr),
+            0xff10..=0xff3f => self.apu.read(addr),
 
-            0xff0f => unimplemented!("{}", addr),
+            0xff0f => self.interrupts.read_if(),
 
-            0xff46 => unimplemented!("{}", addr),
+            0xff46 => self.ppu_dma,
 
-            0xfe00..=0xfeff | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff68..=0xff69 | 0xff4f => {
-                unimplemented!("{}", addr)
+            0xfe00..=0xfeff | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff68..=0xff6b | 0xff4f => {
+                self.ppu.read(addr)
             }
 
-            0xff4d => 0, // Speedswitch
-            0xff70 => unimplemented!("{}", addr),
+            0xff51..=0xff55 if self.cgb => self.hdma.read(addr),
+
+            0xff4d if self.cgb => {
+                0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8
+            }
+            0xff56 => self.ir.read(),
+            0xff70 if self.cgb => 0xF8 | self.svbk,
             0xff80..=0xfffe => self.vram[(addr - 0xFF80) as usize],
-            0xffff => unimplemented!("{}", addr),
-            _ => panic!("Read: addr not in range: 0x{:x}", addr),
+            0xffff => self.interrupts.read_ie(),
+            // Cartridge RAM we don't have and unused IO, nothing drives the bus so it reads high
+            _ => 0xFF,
         }
     }
 
     #[inline]
-    unsafe fn write_mem(&mut self, addr: u16, val: u8) {
+    fn write_bus(&mut self, addr: u16, val: u8) {
         match addr {
-            0x0000..=0x7fff => unimplemented!("{}", addr),
-            0x8000..=0x9fff => unimplemented!("{}", addr),
-            0xa000..=0xbfff => unimplemented!("{}", addr),
+            0x2000..=0x3fff => self.rom_bank = ((val & 0x1F) as usize).max(1),
+            0x0000..=0x7fff => {} // MBC registers we don't emulate
+            0x8000..=0x9fff => self.ppu.write(addr, val),
             0xc000..=0xcfff => self.ram[(addr - 0xC000) as usize] = val,
-            0xd000..=0xdfff => self.ram[(addr - 0xC000) as usize + self.ram_offset] = val,
-            0xe000..=0xfdff => self.write_mem(addr - 0xE000 + 0xC000, val),
+            0xd000..=0xdfff => self.ram[(addr - 0xD000) as usize + self.ram_offset] = val,
+            0xe000..=0xfdff => self.write_bus(addr - 0xE000 + 0xC000, val),
 
-            0xff00 => unimplemented!("{} {}", addr, val),
+            0xff00 => self.interrupts.request(self.joypad.write(val)),
 
-            0xff01..=0xff02 => unimplemented!("Serial"),
-            0xff04..=0xff07 => unimplemented!("{}", addr),
+            0xff01..=0xff02 => self.serial.write(addr, val),
+            0xff04..=0xff07 => self.timer.write(addr, val),
 
-            0xff10..=0xff3f => unimplemented!("{}", addr),
+            0xff10..=0xff3f => self.apu.write(addr, val),
 
-            0xff0f => self.int_flags = val,
+            0xff0f => self.interrupts.write_if(val),
 
             0xff46 => {
                 self.ppu_dma = val;
-                unimplemented!("{}", addr)
+                self.start_oam_dma(val)
             }
 
-            0xfe00..=0xfeff | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff68..=0xff69 | 0xff4f => {
-                unimplemented!("{}", addr)
+            0xfe00..=0xfeff | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff68..=0xff6b | 0xff4f => {
+                self.ppu.write(addr, val)
             }
 
-            0xff4d => {} // Speedswitch
-            0xff70 => {
-                self.svbk = val & 0b111;
-                unimplemented!("{}", addr)
-                // self.update_ram_offset()
+            0xff51..=0xff55 if self.cgb => {
+                // True when the write to HDMA5 starts a general purpose copy
+                let general = self.hdma.write(addr, val);
+                if general {
+                    self.run_general_dma()
+                }
             }
 
-            0xff7f => {} // TETRIS writes to this address for some reason
+            0xff4d if self.cgb => self.speed_switch_armed = val & 0x01 != 0,
+            0xff56 => self.ir.write(val),
+            0xff70 if self.cgb => {
+                self.svbk = val & 0b111;
+                self.update_ram_offset()
+            }
 
             0xff80..=0xfffe => self.vram[(addr - 0xFF80) as usize] = val,
-            0xffff => self.int_enable = val,
-            _ => panic!("Write: addr not in range: 0x{:x} - val: 0x{:x}", addr, val),
+            0xffff => self.interrupts.write_ie(val),
+            // Cartridge RAM we don't have and unused IO, TETRIS writes to 0xFF7F for some reason
+            _ => {}
         }
     }
 
+    /// Copies 0xXX00-0xXX9F into OAM, either right away or a byte per M-cycle from `clock`.
     #[inline]
-    unsafe fn handle_interrupt(&mut self) -> u32 {
-        let ints = self.int_flags & self.int_enable;
-
-        if self.halted {
-            self.halted = ints == 0;
+    fn start_oam_dma(&mut self, page: u8) {
+        match self.oam_dma.mode() {
+            OamDmaMode::Instant => {
+                let source = OamDma::source_of(page);
+                for i in 0..OAM_DMA_LENGTH as u16 {
+                    let val = self.read_bus(source + i);
+                    self.ppu.write_oam(i as usize, val);
+                }
+            }
+            OamDmaMode::Accurate => self.oam_dma.start(page),
         }
+    }
 
-        if !self.ime {
-            return 0;
-        }
+    /// Copies the next HDMA block into VRAM and stalls the CPU for it. Returns false once there's
+    /// nothing left to copy.
+    #[inline]
+    fn copy_hdma_block(&mut self) -> bool {
+        let (source, dest) = match self.hdma.next_block() {
+            Some(block) => block,
+            None => return false,
+        };
 
-        if ints == 0 {
-            return 0;
+        for i in 0..HDMA_BLOCK_SIZE {
+            let val = self.read_bus(source.wrapping_add(i));
+            self.ppu.write_vram(dest + i, val);
         }
+        self.stall += Hdma::block_cycles(self.double_speed);
+        true
+    }
 
-        self.ime = false;
-
-        let int = ints.trailing_zeros();
-        let int_handler = {
-            match int {
-                0 => 0x40, // VBLANK
-                1 => 0x48, // LCDC STATUS
-                2 => 0x50, // TIMER OVERFLOW
-                3 => 0x58, // SERIAL TRANSFER COMPLETE
-                4 => 0x60, // P10-P13 INPUT SIGNAL
-                _ => panic!("Invalid interrupt {:x}", int),
-            }
+    /// General purpose DMA moves everything in one go while the CPU is halted.
+    #[inline]
+    fn run_general_dma(&mut self) {
+        while self.copy_hdma_block() {}
+    }
+
+    /// Samples the buttons, raising the joypad interrupt if one was just pressed.
+    pub fn poll_input<I: InputSource>(&mut self, input: &mut I) {
+        self.interrupts.request(self.joypad.poll(input));
+    }
+
+    /// Holds down Game Boy buttons that were already mapped from the board's, e.g. by
+    /// io/chords.rs.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.interrupts.request(self.joypad.set_held(buttons));
+    }
+
+    /// Lets the serial port swap a byte with `link` if a transfer is due. Call it after every
+    /// `execute`, with `NoCable` if nothing's plugged in.
+    pub fn service_link<T: LinkTransport + ?Sized>(&mut self, link: &mut T) {
+        self.interrupts.request(self.serial.service(link));
+    }
+
+    /// Syncs the IR port's LED and sensor with `ir`. Call it after every `execute`, with `NoIr` if
+    /// there's nobody to talk to.
+    pub fn service_ir<T: IrTransport + ?Sized>(&mut self, ir: &mut T) {
+        self.ir.service(ir);
+    }
+
+    /// Sets the sample rate and layout audio comes out in, which starts the mix over, mutes
+    /// and all.
+    pub fn set_audio_format(&mut self, rate: u32, layout: Layout) {
+        self.mixer = Mixer::new(rate, layout);
+    }
+
+    /// Hands `sink` the audio mixed since the last call. Call it often enough that it doesn't
+    /// pile up past a few hundred samples, or the oldest gets dropped.
+    pub fn drain_audio<S: AudioSink + ?Sized>(&mut self, sink: &mut S) {
+        self.mixer.drain(sink);
+    }
+
+    /// Leaves `channel`, 0-3, out of what `drain_audio` hands over, or puts it back.
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    /// Starts or stops recording each channel on its own for `drain_audio_with_capture`.
+    pub fn set_audio_capture(&mut self, capturing: bool) {
+        self.mixer.set_capturing(capturing);
+    }
+
+    /// `drain_audio`, plus each channel's share of the same samples into `capture`.
+    pub fn drain_audio_with_capture<S: AudioSink + ?Sized, C: CaptureSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        capture: &mut C,
+    ) {
+        self.mixer.drain_with_capture(sink, capture);
+    }
+
+    /// Returns true once per frame, when the PPU's done with it and gone into VBlank.
+    pub fn frame_ready(&mut self) -> bool {
+        self.ppu.frame_ready()
+    }
+
+    /// The screen in BGR555, `SCREEN_WIDTH` pixels a row. Between `frame_ready` and the next
+    /// line being drawn it's all one frame.
+    pub fn frame(&self) -> &[u16] {
+        &self.ppu.frame
+    }
+
+    /// Draws `scope`'s traces over the frame, for seeing what each channel's doing. Do it right
+    /// after `frame_ready`, the next frame draws over it line by line.
+    pub fn overlay_scope<const N: usize>(&mut self, scope: &Scope<N>) {
+        scope.render(&mut self.ppu.frame, SCREEN_WIDTH);
+    }
+
+    /// Whether an illegal opcode has locked the CPU up. Everything else carries on, so frames
+    /// still come out, but the CPU won't run another instruction.
+    #[inline]
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether STOP has everything stopped until a button's pressed. Nothing's going to happen
+    /// until then, so this is a good time to sleep.
+    #[inline]
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Picks between instant and cycle accurate OAM DMA.
+    pub fn set_oam_dma_mode(&mut self, mode: OamDmaMode) {
+        self.oam_dma.set_mode(mode);
+    }
+
+    /// Picks between running instructions whole and M-cycle by M-cycle. Takes effect from the
+    /// next `execute`.
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+        self.mode = mode;
+    }
+
+    /// Maps the WRAM bank SVBK selects into 0xD000-0xDFFF. Bank 0 is always at 0xC000, so
+    /// asking for it gets you bank 1.
+    #[inline]
+    fn update_ram_offset(&mut self) {
+        let bank = if self.svbk == 0 {
+            1
+        } else {
+            self.svbk as usize
         };
+        self.ram_offset = bank * RAM_BANK_SIZE;
+    }
 
-        self.int_flags &= 0xff << (int + 1);
+    /// Takes the highest priority pending interrupt if IME allows, returning the T-cycles that
+    /// took or 0 if there wasn't one.
+    #[inline]
+    fn dispatch_interrupt(&mut self) -> u32 {
+        if !self.interrupts.should_dispatch() {
+            return 0;
+        }
+        self.interrupts.disable();
+        self.m_cycle();
+        self.m_cycle();
 
-        let pc = self.pc;
-        instructions::push_u16(self, pc);
-        self.pc = int_handler;
+        // Which one gets taken isn't settled until the high byte is pushed, since that can
+        // land on IE
+        let pc = self.regs.pc;
+        instructions::push_u8(self, (pc >> 8) as u8);
+        let vector = self.interrupts.acknowledge();
+        instructions::push_u8(self, pc as u8);
+        self.regs.pc = vector;
 
-        20
+        DISPATCH_CYCLES
+    }
+
+    /// Runs instructions until at least `cycles` T-cycles have gone by, returning how many
+    /// actually did. Stops early if the CPU locks up.
+    pub fn run(&mut self, cycles: u32) -> Result<u32, EmuError> {
+        let mut ran = 0;
+        while ran < cycles {
+            ran += self.execute()?;
+        }
+        Ok(ran)
     }
 
     // Sue me
+    /// Runs one instruction and returns how many T-cycles it took, which in double speed mode
+    /// are CPU cycles at twice the usual rate.
+    ///
+    /// An illegal opcode locks the CPU up and comes back as an error, once. After that this just
+    /// idles an M-cycle at a time like the hardware would, with the display still going.
     #[inline]
-    pub unsafe fn execute(&mut self) -> u32 {
+    pub fn execute(&mut self) -> Result<u32, EmuError> {
+        if self.locked {
+            self.clock(LOCKED_CYCLES);
+            return Ok(LOCKED_CYCLES);
+        }
+
+        if self.stopped {
+            if !self.joypad.pressed() {
+                return Ok(STOPPED_CYCLES);
+            }
+            self.stopped = false;
+        }
+
+        // Halted, the CPU idles an M-cycle at a time until something's pending, IME or not.
+        // Waking up to take an interrupt costs an extra M-cycle on top of the dispatch
+        let mut wake = 0;
+        if self.halted {
+            if self.interrupts.pending() == 0 {
+                self.clock(HALT_CYCLES);
+                return Ok(HALT_CYCLES);
+            }
+            self.halted = false;
+            if self.interrupts.should_dispatch() {
+                self.m_cycle();
+                wake = HALT_CYCLES;
+            }
+        }
+
+        let dispatched = self.dispatch_interrupt();
+        if dispatched > 0 {
+            return Ok(self.finish(dispatched + wake));
+        }
+
+        let pc = self.regs.pc;
         let (prefixed, byte) = self.step();
 
         let timing = if prefixed {
@@ -232,25 +562,96 @@ impl CPU {
             Timing::Flag => OPCODE_COND_TIMES[byte as usize] as u32,
             Timing::Cb(x) => x,
         };
+        self.interrupts.instruction_done();
 
-        cycles * 4
+        let stall = core::mem::take(&mut self.stall);
+        let cycles = self.finish(cycles * 4 + stall);
+        if self.locked {
+            return Err(EmuError::IllegalOpcode { pc, opcode: byte });
+        }
+
+        let pause = core::mem::take(&mut self.pause);
+        self.clock_fixed(pause);
+        Ok(cycles + pause)
     }
 
+    /// An M-cycle of the current instruction, spent on the bus or otherwise. In accurate mode
+    /// everything else is caught up right then, in fast mode it waits for `finish`.
     #[inline]
-    unsafe fn execute_in(&mut self, opcode: u8) -> Timing {
+    fn m_cycle(&mut self) {
+        if self.mode == ExecutionMode::Accurate {
+            self.clock(4);
+            self.ticked += 4;
+        }
+    }
+
+    /// Clocks whatever's left of an instruction that took `cycles` CPU T-cycles once the
+    /// M-cycles already ticked through are taken off, and returns how long it really took.
+    #[inline]
+    fn finish(&mut self, cycles: u32) -> u32 {
+        let ticked = core::mem::take(&mut self.ticked);
+        let cycles = cycles.max(ticked);
+        self.clock(cycles - ticked);
+        cycles
+    }
+
+    /// Converts CPU T-cycles into ones at the fixed 4MHz the PPU and APU run at.
+    #[inline]
+    fn fixed_cycles(&self, cycles: u32) -> u32 {
+        if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        }
+    }
+
+    /// Advances everything that isn't the CPU by `cycles` CPU clocks. The timer, serial port and
+    /// OAM DMA run off the CPU's clock and speed up with it in double speed mode, everything
+    /// else keeps going at the same rate.
+    #[inline]
+    fn clock(&mut self, cycles: u32) {
+        self.interrupts.request(self.timer.tick(cycles));
+        self.serial.tick(cycles);
+        self.clock_fixed(cycles);
+
+        // HBlank DMA, the stall gets paid on the next instruction
+        if self.ppu.hblank_started() && self.hdma.hblank_pending() {
+            self.copy_hdma_block();
+        }
+
+        for _ in 0..self.oam_dma.tick(cycles) {
+            let (source, index) = self.oam_dma.next();
+            let val = self.read_bus(source);
+            self.ppu.write_oam(index, val);
+            self.oam_dma.transferred(val);
+        }
+    }
+
+    /// Advances the parts that run at a fixed rate whatever the CPU's doing by `cycles` CPU
+    /// clocks.
+    #[inline]
+    fn clock_fixed(&mut self, cycles: u32) {
+        let cycles = self.fixed_cycles(cycles);
+        self.ir.tick(cycles);
+        self.apu.tick(cycles);
+        self.mixer.advance(&self.apu, cycles);
+        self.interrupts.request(self.ppu.tick(cycles));
+    }
+
+    #[inline]
+    fn execute_in(&mut self, opcode: u8) -> Timing {
         use Flag::*;
-        use Register::*;
 
         match opcode {
             0x00 => Timing::Default,
-            0x01 => instructions::ld::<u8, _, _>(self, BC, D16),
+            0x01 => instructions::ld::<u16, _, _>(self, BC, D16),
             0x02 => instructions::ld::<u8, _, _>(self, Mem(BC), A),
             0x03 => instructions::inc_16(self, BC),
             0x04 => instructions::inc_8(self, B),
             0x05 => instructions::dec_8(self, B),
             0x06 => instructions::ld::<u8, _, _>(self, B, D8),
             0x07 => instructions::rlca(self),
-            0x08 => instructions::ld::<u8, _, _>(self, Mem(D16), SP),
+            0x08 => instructions::ld::<u16, _, _>(self, Mem(D16), SP),
             0x09 => instructions::add_16(self, HL, BC),
             0x0a => instructions::ld::<u8, _, _>(self, A, Mem(BC)),
             0x0b => instructions::dec_16(self, BC),
@@ -258,8 +659,8 @@ impl CPU {
             0x0d => instructions::dec_8(self, C),
             0x0e => instructions::ld::<u8, _, _>(self, C, D8),
             0x0f => instructions::rrca(self),
-            0x10 => instructions::stop(),
-            0x11 => instructions::ld::<u8, _, _>(self, DE, D16),
+            0x10 => instructions::stop(self),
+            0x11 => instructions::ld::<u16, _, _>(self, DE, D16),
             0x12 => instructions::ld::<u8, _, _>(self, Mem(DE), A),
             0x13 => instructions::inc_16(self, DE),
             0x14 => instructions::inc_8(self, D),
@@ -275,7 +676,7 @@ impl CPU {
             0x1e => instructions::ld::<u8, _, _>(self, E, D8),
             0x1f => instructions::rra(self),
             0x20 => instructions::jr(self, NZ, D8),
-            0x21 => instructions::ld::<u8, _, _>(self, HL, D16),
+            0x21 => instructions::ld::<u16, _, _>(self, HL, D16),
             0x22 => instructions::ldi::<u8, _, _>(self, Mem(HL), A, HL),
             0x23 => instructions::inc_16(self, HL),
             0x24 => instructions::inc_8(self, H),
@@ -291,7 +692,7 @@ impl CPU {
             0x2e => instructions::ld::<u8, _, _>(self, L, D8),
             0x2f => instructions::cpl(self),
             0x30 => instructions::jr(self, NC, D8),
-            0x31 => instructions::ld::<u8, _, _>(self, SP, D16),
+            0x31 => instructions::ld::<u16, _, _>(self, SP, D16),
             0x32 => instructions::ldd::<u8, _, _>(self, Mem(HL), A, HL),
             0x33 => instructions::inc_16(self, SP),
             0x34 => instructions::inc_8(self, Mem(HL)),
@@ -482,20 +883,20 @@ impl CPU {
             0xf6 => instructions::or(self, D8),
             0xf7 => instructions::rst(self, 0x30),
             0xf8 => instructions::ld_hl_sp(self),
-            0xf9 => instructions::ld::<u8, _, _>(self, SP, HL),
+            0xf9 => instructions::ld::<u16, _, _>(self, SP, HL),
             0xfa => instructions::ld::<u8, _, _>(self, A, Mem(D16)),
             0xfb => instructions::ei(self),
             0xfe => instructions::cp(self, D8),
             0xff => instructions::rst(self, 0x38),
 
-            _ => panic!("Invalid opcode: 0x{:x}\n{:#?}", opcode, self.flag),
+            0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb..=0xed | 0xf4 | 0xfc | 0xfd => {
+                instructions::lock_up(self)
+            }
         }
     }
 
     #[inline]
-    unsafe fn execute_cb(&mut self, opcode: u8) -> Timing {
-        use Flag::*;
-        use Register::*;
+    fn execute_cb(&mut self, opcode: u8) -> Timing {
 
         match opcode {
             0x00 => instructions::rlc(self, B),
@@ -754,8 +1155,6 @@ impl CPU {
             0xfd => instructions::set(self, 7, L),
             0xfe => instructions::set(self, 7, Mem(HL)),
             0xff => instructions::set(self, 7, A),
-
-            _ => panic!("CB opcode out of range: 0x{:x}", opcode),
         };
 
         Timing::Cb(CB_OPCODE_TIMES[opcode as usize] as u32)
@@ -765,124 +1164,31 @@ impl CPU {
 impl Flagd for CPU {
     #[inline]
     fn status(&self, f: Flag) -> bool {
-        match f {
-            Flag::NF => true,
-            Flag::Z => self.flag >> ZERO_FLAG_BYTE_POSITION == 1,
-            Flag::NZ => self.flag >> ZERO_FLAG_BYTE_POSITION == 0,
-            Flag::CY => self.flag >> CARRY_FLAG_BYTE_POSITION == 1,
-            Flag::NC => self.flag >> CARRY_FLAG_BYTE_POSITION == 0,
-            Flag::HC => self.flag >> HALF_CARRY_FLAG_BYTE_POSITION == 1,
-            Flag::S => self.flag >> SUBTRACT_FLAG_BYTE_POSITION == 1,
-            _ => panic!("Bad status call: {:016b}: {:016b}", self.pc, self.sp),
-        }
+        self.regs.status(f)
     }
 
     #[inline]
     fn zero(&mut self, b: bool) {
-        if b {
-            self.flag |= 0b1000_0000
-        } else {
-            self.flag &= 0b0111_1111
-        }
+        self.regs.zero(b)
     }
 
     #[inline]
     fn subtract(&mut self, b: bool) {
-        if b {
-            self.flag |= 0b0100_0000
-        } else {
-            self.flag &= 0b1011_1111
-        }
+        self.regs.subtract(b)
     }
 
     #[inline]
     fn half_carry(&mut self, b: bool) {
-        if b {
-            self.flag |= 0b0010_0000
-        } else {
-            self.flag &= 0b1101_1111
-        }
+        self.regs.half_carry(b)
     }
 
     #[inline]
     fn carry(&mut self, b: bool) {
-        if b {
-            self.flag |= 0b0001_0000
-        } else {
-            self.flag &= 0b1110_1111
-        }
+        self.regs.carry(b)
     }
 
     #[inline]
     fn set_flags(&mut self, zero: bool, subtract: bool, half_carry: bool, carry: bool) {
-        self.zero(zero);
-        self.subtract(subtract);
-        self.half_carry(half_carry);
-        self.carry(carry);
-    }
-}
-
-impl Registerd8 for CPU {
-    /// Returns a pointer to the specified Register, will panic if asked for a non-8-bit register
-    ///
-    /// # Examples
-    /// ```no_run
-    /// use cpu::{CPU, Register8};
-    ///
-    /// let mut cpu = CPU::new();
-    /// let mut regi = cpu.addr(Register::H);
-    ///
-    /// *regi = 0b1010_0100;
-    ///
-    /// assert_eq!(*cpu.addr(Register::H), 0b1010_0100);
-    /// ```
-    #[inline]
-    unsafe fn addr(&mut self, r: &Register) -> *mut u8 {
-        match r {
-            Register::D8 => &mut self.pc.to_be_bytes()[0],
-            Register::A => &mut self.a,
-            Register::F => &mut self.flag,
-            Register::B => &mut self.b,
-            Register::C => &mut self.c,
-            Register::D => &mut self.d,
-            Register::E => &mut self.e,
-            Register::H => &mut self.h,
-            Register::L => &mut self.l,
-            _ => {
-                panic!("Invalid call to addr");
-            }
-        }
-    }
-}
-
-impl Registerd16 for CPU {
-    /// Returns a pointer to the specified Register, will panic if asked for a non-16-bit register
-    ///
-    /// # Examples
-    /// ```no_run
-    /// use cpu::{CPU, Register};
-    ///
-    /// let mut cpu = CPU::new();
-    /// let mut regi = cpu.vaddr(Register::HL);
-    ///
-    /// *regi = 0b0000_0001_1010_0100;
-    ///
-    /// assert_eq!(*cpu.addr(Register::HL), 0b0000_0001_1010_0100);
-    /// ```
-    #[inline]
-    unsafe fn vaddr(&mut self, r: &Register) -> *mut u16 {
-        match r {
-            Register::AF => (&mut self.flag as *mut u8) as *mut u16,
-            Register::BC => (&mut self.c as *mut u8) as *mut u16,
-            Register::DE => (&mut self.e as *mut u8) as *mut u16,
-            Register::HL => (&mut self.l as *mut u8) as *mut u16,
-            Register::HLi => (&mut self.l as *mut u8) as *mut u16,
-            Register::HLd => (&mut self.l as *mut u8) as *mut u16,
-            Register::SP => &mut self.sp,
-            Register::D16 => &mut self.pc,
-            _ => {
-                panic!("Invalid call to vaddr");
-            }
-        }
+        self.regs.set_flags(zero, subtract, half_carry, carry)
     }
 }
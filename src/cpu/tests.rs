@@ -1,56 +1,397 @@
-use super::{Instruction, Register, CPU};
+extern crate std;
 
-/* well, if we could test that would be nice, TODO this later
+use std::boxed::Box;
 
-   Compiling gbc-m4 v0.1.0
-error[E0463]: can't find crate for `test`
-  --> src/cpu/tests.rs:22:1
-   |
-47 | / fn test_add_8() {
-48 | |     let mut cpu = cpu();
-49 | |
-50 | |     unsafe {
-...  |
-56 | |     assert_eq!(cpu.a, 9);
-57 | | }
-   | |_^ can't find crate
-   |
-   = note: this error originates in an attribute macro (in Nightly builds, run with -Z macro-backtrace for more info)
+use super::{EmuError, ExecutionMode, CPU};
+use crate::apu::{AudioSink, Scope, SCOPE_COLORS};
+use crate::harness::{cycles_per_line, program};
+use crate::io::joypad;
+use crate::ppu::SCREEN_WIDTH;
 
-error: aborting due to previous error
+#[test]
+fn test_add_8() {
+    // ADD A,D / ADD A,D
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(program(&[0x82, 0x82]));
+    cpu.regs.a = 3;
+    cpu.regs.d = 3;
 
-For more information about this error, try `rustc --explain E0463`.
-error: could not compile `gbc-m4`
+    for _i in 0..2 {
+        cpu.execute().unwrap();
+    }
 
-To learn more, run the command again with --verbose.
+    assert_eq!(cpu.regs.a, 9);
+}
 
- */
+#[test]
+fn speed_switch_doubles_the_cpu() {
+    // loop: JR loop
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(program(&[0x18, 0xFE]));
+    assert_eq!(cycles_per_line(&mut cpu), 456);
 
-fn cpu() -> CPU {
-    CPU {
-        a: 0,
-        flag: 0b0000_0000,
-        b: 1,
-        c: 2,
-        d: 3,
-        e: 4,
-        h: 5,
-        l: 6,
-        pc: 0,
-        sp: 0,
-        bus: [0u8; 65535],
+    // LD A,1 / LDH (KEY1),A / STOP / loop: JR loop
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(program(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE]));
+    assert_eq!(cpu.peek(0xff4d), 0x7E);
+    for _ in 0..2 {
+        cpu.execute().unwrap();
     }
+    assert_eq!(cpu.peek(0xff4d), 0x7F);
+
+    let cycles = cpu.execute().unwrap();
+    assert!(cycles >= 2050 * 4);
+    assert_eq!(cpu.peek(0xff4d), 0xFE);
+    assert_eq!(cpu.pc(), 0x106);
+    // DIV was reset and held through the switch
+    assert_eq!(cpu.peek(0xff04), 0);
+
+    assert_eq!(cycles_per_line(&mut cpu), 912);
 }
 
 #[test]
-fn test_add_8() {
-    let mut cpu = cpu();
+fn stop_waits_for_a_button() {
+    // LD A,0x10 / LDH (P1),A / STOP / INC B / loop: JR loop
+    let rom = program(&[0x3E, 0x10, 0xE0, 0x00, 0x10, 0x00, 0x04, 0x18, 0xFE]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+
+    for _ in 0..3 {
+        cpu.execute().unwrap();
+    }
+    assert!(cpu.stopped());
+    let ly = cpu.peek(0xff44);
+    for _ in 0..1000 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.pc(), 0x106);
+    assert_eq!(cpu.peek(0xff44), ly);
+
+    // Directions aren't selected, so they don't count
+    cpu.set_buttons(joypad::UP);
+    cpu.execute().unwrap();
+    assert!(cpu.stopped());
+
+    cpu.set_buttons(joypad::A);
+    cpu.execute().unwrap();
+    assert!(!cpu.stopped());
+    assert_eq!(cpu.registers()[2], 1);
+}
+
+#[test]
+fn interrupt_waits_out_ei_and_takes_five_m_cycles() {
+    // LD A,0x04 / LDH (IE),A / LDH (IF),A / EI / INC B / INC B / loop: JR loop
+    let mut rom = program(&[
+        0x3E, 0x04, 0xE0, 0xFF, 0xE0, 0x0F, 0xFB, 0x04, 0x04, 0x18, 0xFE,
+    ])
+    .to_vec();
+    // Timer handler: INC C / loop: JR loop
+    rom[0x50..0x53].copy_from_slice(&[0x0C, 0x18, 0xFE]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(Box::leak(rom.into_boxed_slice()));
+
+    for _ in 0..5 {
+        cpu.execute().unwrap();
+    }
+    // The INC B after EI still ran
+    assert_eq!(cpu.registers()[2], 1);
+    assert_eq!(cpu.pc(), 0x108);
+
+    assert_eq!(cpu.execute().unwrap(), 20);
+    assert_eq!(cpu.pc(), 0x50);
+    assert_eq!(cpu.peek(0xfffd), 0x01);
+    assert_eq!(cpu.peek(0xfffc), 0x08);
+    assert_eq!(cpu.peek(0xff0f), 0xE0);
+}
+
+#[test]
+fn pushing_onto_ie_cancels_the_interrupt() {
+    // INC SP / INC SP / LD A,0x04 / LDH (IE),A / LDH (IF),A / EI / NOP / loop: JR loop
+    let rom = program(&[
+        0x33, 0x33, 0x3E, 0x04, 0xE0, 0xFF, 0xE0, 0x0F, 0xFB, 0x00, 0x18, 0xFE,
+    ]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+
+    for _ in 0..7 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.execute().unwrap(), 20);
+
+    // PC's high byte landed on IE and turned the timer off, so there was nothing to take
+    assert_eq!(cpu.peek(0xffff), 0x01);
+    assert_eq!(cpu.pc(), 0x0000);
+    assert_eq!(cpu.peek(0xff0f), 0xE4);
+}
+
+#[test]
+fn halt_idles_until_an_interrupt() {
+    // LD A,0x04 / LDH (IE),A / LD A,0x05 / LDH (TAC),A / EI / HALT / INC B / loop: JR loop
+    let mut rom = program(&[
+        0x3E, 0x04, 0xE0, 0xFF, 0x3E, 0x05, 0xE0, 0x07, 0xFB, 0x76, 0x04, 0x18, 0xFE,
+    ])
+    .to_vec();
+    // Timer handler: INC C / loop: JR loop
+    rom[0x50..0x53].copy_from_slice(&[0x0C, 0x18, 0xFE]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(Box::leak(rom.into_boxed_slice()));
+
+    for _ in 0..6 {
+        cpu.execute().unwrap();
+    }
+    let mut idle = 0;
+    while cpu.pc() == 0x10A {
+        assert_eq!(cpu.execute().unwrap() % 4, 0);
+        idle += 1;
+        assert!(idle < 10_000);
+    }
+    // TIMA ticks every 16 T-cycles, so it takes about 256 of those to overflow
+    assert!(idle > 900);
+    assert_eq!(cpu.pc(), 0x50);
+    assert_eq!(cpu.registers()[2], 0);
+    assert_eq!(cpu.peek(0xfffc), 0x0A);
+}
+
+#[test]
+fn halt_wakes_without_ime() {
+    // LD A,0x04 / LDH (IE),A / LD A,0x05 / LDH (TAC),A / HALT / INC B / loop: JR loop
+    let rom = program(&[
+        0x3E, 0x04, 0xE0, 0xFF, 0x3E, 0x05, 0xE0, 0x07, 0x76, 0x04, 0x18, 0xFE,
+    ]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+
+    for _ in 0..5 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.pc(), 0x109);
+    for _ in 0..2000 {
+        cpu.execute().unwrap();
+    }
+
+    // Carried on after HALT without taking the interrupt, which is still pending
+    assert_eq!(cpu.registers()[2], 1);
+    assert_eq!(cpu.pc(), 0x10A);
+    assert_eq!(cpu.peek(0xff0f), 0xE4);
+}
+
+#[test]
+fn halt_bug_reads_the_next_opcode_twice() {
+    // LD A,0x04 / LDH (IE),A / LDH (IF),A / HALT / INC B / loop: JR loop
+    let rom = program(&[0x3E, 0x04, 0xE0, 0xFF, 0xE0, 0x0F, 0x76, 0x04, 0x18, 0xFE]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+
+    for _ in 0..8 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.registers()[2], 2);
+    assert_eq!(cpu.pc(), 0x108);
+}
+
+/// What DIV reads after being reset by a two M-cycle write then read back by a three M-cycle
+/// one, with `nops` in between.
+fn div_after(mode: ExecutionMode, nops: usize) -> u8 {
+    // LD H,0xFF / LD L,0x04 / LD (HL),A / NOP... / LDH A,(DIV)
+    let mut code = std::vec![0x26, 0xFF, 0x2E, 0x04, 0x77];
+    code.extend(std::iter::repeat_n(0x00, nops));
+    code.extend([0xF0, 0x04]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.set_execution_mode(mode);
+    cpu.load_rom(program(&code));
+
+    for _ in 0..nops + 4 {
+        cpu.execute().unwrap();
+    }
+    cpu.registers()[0]
+}
+
+#[test]
+fn accurate_mode_reads_and_writes_mid_instruction() {
+    // The write lands at the end of its second M-cycle and the read at the end of its third, so
+    // 61 NOPs put exactly 256 T-cycles between them. Doing them up front leaves the read short
+    assert_eq!(div_after(ExecutionMode::Accurate, 61), 1);
+    assert_eq!(div_after(ExecutionMode::Fast, 61), 0);
+    assert_eq!(div_after(ExecutionMode::Fast, 62), 1);
+}
+
+#[test]
+fn both_modes_take_as_long() {
+    // PUSH BC / CALL 0x0110, then RST 0x38 from there and JR to itself
+    let mut rom = program(&[0xC5, 0xCD, 0x10, 0x01]).to_vec();
+    rom[0x110] = 0xFF;
+    rom[0x38..0x3A].copy_from_slice(&[0x18, 0xFE]);
+    let rom: &'static [u8] = Box::leak(rom.into_boxed_slice());
+
+    let mut fast = Box::new(CPU::new(true));
+    fast.load_rom(rom);
+    let mut accurate = Box::new(CPU::new(true));
+    accurate.set_execution_mode(ExecutionMode::Accurate);
+    accurate.load_rom(rom);
+
+    for _ in 0..4 {
+        let cycles = fast.execute().unwrap();
+        assert_eq!(accurate.execute().unwrap(), cycles);
+        assert_eq!(accurate.pc(), fast.pc());
+    }
+    assert_eq!(accurate.pc(), 0x38);
+    assert_eq!(cycles_per_line(&mut accurate), 456);
+    assert_eq!(accurate.peek(0xfff9), 0x01);
+    assert_eq!(accurate.peek(0xfff8), 0x11);
+}
+
+#[test]
+fn illegal_opcode_locks_up_but_the_display_keeps_going() {
+    // INC B / illegal / INC B
+    let rom = program(&[0x04, 0xD3, 0x04]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+
+    assert_eq!(
+        cpu.run(1000),
+        Err(EmuError::IllegalOpcode {
+            pc: 0x101,
+            opcode: 0xD3
+        })
+    );
+    assert!(cpu.locked());
+
+    let ly = cpu.peek(0xff44);
+    assert_eq!(cpu.run(456 * 2), Ok(456 * 2));
+    assert_ne!(cpu.peek(0xff44), ly);
+    assert_eq!(cpu.registers()[2], 1);
+    assert_eq!(cpu.pc(), 0x102);
+}
+
+#[test]
+fn unmapped_reads_float_high() {
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(program(&[]));
+    for addr in [0xa000, 0xbfff, 0xff03, 0xff4c, 0xff7f] {
+        assert_eq!(cpu.peek(addr), 0xFF);
+    }
+}
+
+#[test]
+fn pairs_load_pop_and_return() {
+    // LD BC,0x12FF / PUSH BC / POP AF / CALL 0x0110 / INC B / loop: JR loop
+    let mut rom = program(&[
+        0x01, 0xFF, 0x12, 0xC5, 0xF1, 0xCD, 0x10, 0x01, 0x04, 0x18, 0xFE,
+    ])
+    .to_vec();
+    // INC C / RET
+    rom[0x110..0x112].copy_from_slice(&[0x0C, 0xC9]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(Box::leak(rom.into_boxed_slice()));
+
+    for _ in 0..3 {
+        cpu.execute().unwrap();
+    }
+    // F's low nibble doesn't survive the POP
+    assert_eq!(cpu.registers()[..2], [0x12, 0xF0]);
+
+    for _ in 0..4 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.pc(), 0x109);
+    assert_eq!(cpu.registers()[..4], [0x12, 0x10, 0x13, 0x00]);
+}
+
+#[test]
+fn svbk_banks_wram_at_0xd000() {
+    let mut cpu = Box::new(CPU::new(true));
+    assert_eq!(cpu.peek(0xff70), 0xF8);
+    for bank in 1..8 {
+        cpu.write_bus(0xff70, bank);
+        cpu.write_bus(0xd000, bank * 0x11);
+    }
+
+    // Bank 0 can't be put at 0xD000, asking for it gets bank 1
+    cpu.write_bus(0xff70, 0x00);
+    assert_eq!(cpu.peek(0xff70), 0xF8);
+    assert_eq!(cpu.peek(0xd000), 0x11);
+
+    for bank in 1..8 {
+        cpu.write_bus(0xff70, 0xF0 | bank);
+        assert_eq!(cpu.peek(0xff70), 0xF8 | bank);
+        assert_eq!(cpu.peek(0xd000), bank * 0x11);
+        // Echo RAM follows along
+        assert_eq!(cpu.peek(0xf000), bank * 0x11);
+    }
+
+    // 0xC000-0xCFFF is always bank 0
+    cpu.write_bus(0xc000, 0xAB);
+    cpu.write_bus(0xff70, 0x03);
+    assert_eq!(cpu.peek(0xc000), 0xAB);
+}
+
+#[test]
+fn dmg_has_no_cgb_registers() {
+    let mut cpu = Box::new(CPU::new(false));
+    cpu.write_bus(0xd000, 0x11);
+    cpu.write_bus(0xff70, 0x03);
+    cpu.write_bus(0xd000, 0x33);
+    cpu.write_bus(0xff70, 0x01);
+    assert_eq!(cpu.peek(0xd000), 0x33);
+
+    cpu.write_bus(0xff4f, 0x01);
+    for addr in [0xff4d, 0xff4f, 0xff55, 0xff56, 0xff69, 0xff70] {
+        assert_eq!(cpu.peek(addr), 0xFF, "{:04x}", addr);
+    }
+}
+
+#[test]
+fn boots_as_either_model() {
+    // A is how a game tells which one it's on
+    let dmg = Box::new(CPU::new(false));
+    assert_eq!(
+        dmg.registers(),
+        [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D]
+    );
+    assert_eq!((dmg.regs.sp, dmg.regs.pc), (0xFFFE, 0x0100));
+    let cgb = Box::new(CPU::new(true));
+    assert_eq!(
+        cgb.registers(),
+        [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]
+    );
+    assert_eq!((cgb.regs.sp, cgb.regs.pc), (0xFFFE, 0x0100));
+    assert_eq!(cgb.peek(0xff70), 0xF8);
+}
+
+struct Nowhere;
+
+impl AudioSink for Nowhere {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        samples.len()
+    }
+}
+
+#[test]
+fn scope_draws_over_the_frame() {
+    // NR52 on, NR50 full, NR51 everywhere, NR22 full volume, NR24 trigger at 512 Hz, loop
+    let rom = program(&[
+        0x3E, 0x80, 0xE0, 0x26, 0x3E, 0x77, 0xE0, 0x24, 0x3E, 0xFF, 0xE0, 0x25, 0x3E, 0xF0, 0xE0,
+        0x17, 0x3E, 0x87, 0xE0, 0x19, 0x18, 0xFE,
+    ]);
+    let mut cpu = Box::new(CPU::new(true));
+    cpu.load_rom(rom);
+    cpu.set_audio_capture(true);
 
-    unsafe {
-        for _i in 0..2 {
-            cpu.execute(Instruction::ADD(Register::D));
-        }
+    let mut scope = Scope::<SCREEN_WIDTH>::new();
+    while !cpu.frame_ready() {
+        cpu.execute().unwrap();
+        cpu.drain_audio_with_capture(&mut Nowhere, &mut scope);
     }
+    assert!(!cpu.frame().contains(&SCOPE_COLORS[1]));
+    cpu.overlay_scope(&scope);
 
-    assert_eq!(cpu.a, 9);
+    // Channel 2's lane is the second quarter of the screen, and a square wave goes all the
+    // way up and down it
+    let lane = 144 / 4;
+    let rows = (lane..lane * 2)
+        .filter(|row| {
+            cpu.frame()[row * SCREEN_WIDTH..(row + 1) * SCREEN_WIDTH].contains(&SCOPE_COLORS[1])
+        })
+        .count();
+    assert!(rows > lane / 2, "{} rows", rows);
 }
@@ -0,0 +1,152 @@
+/// Bytes moved per HDMA block.
+pub const HDMA_BLOCK_SIZE: u16 = 0x10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HdmaState {
+    Idle,
+    /// Copy everything now while the CPU waits
+    General,
+    /// Copy a block at the start of every HBlank
+    HBlank,
+}
+
+/// The CGB VRAM DMA controller behind HDMA1-HDMA5 (0xFF51-0xFF55).
+///
+/// Transfers always move 16 byte blocks from ROM, SRAM or WRAM into the VRAM bank selected by
+/// VBK. The controller only does the bookkeeping, the `CPU` does the copying since it owns
+/// the bus.
+pub struct Hdma {
+    state: HdmaState,
+    /// Source address, the low nibble is always zero
+    source: u16,
+    /// Destination offset into VRAM, 0x0000-0x1FF0
+    dest: u16,
+    /// Blocks left minus one, in the format HDMA5 reports them
+    remaining: u8,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Self {
+            state: HdmaState::Idle,
+            source: 0,
+            dest: 0,
+            remaining: 0x7F,
+        }
+    }
+
+    /// HDMA1-4 are write only, HDMA5 reads back the remaining length with bit 7 set once nothing
+    /// is running.
+    #[inline]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff55 => match self.state {
+                HdmaState::Idle => 0x80 | self.remaining,
+                _ => self.remaining,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes an HDMA register. Returns true when a general purpose transfer was started and the
+    /// CPU should run it to completion right away.
+    #[inline]
+    pub fn write(&mut self, addr: u16, val: u8) -> bool {
+        match addr {
+            0xff51 => self.source = (self.source & 0x00F0) | ((val as u16) << 8),
+            0xff52 => self.source = (self.source & 0xFF00) | (val & 0xF0) as u16,
+            0xff53 => self.dest = (self.dest & 0x00F0) | (((val & 0x1F) as u16) << 8),
+            0xff54 => self.dest = (self.dest & 0x1F00) | (val & 0xF0) as u16,
+            0xff55 => {
+                // Clearing bit 7 mid HBlank transfer stops it, keeping the remaining length
+                if self.state == HdmaState::HBlank && val & 0x80 == 0 {
+                    self.state = HdmaState::Idle;
+                    return false;
+                }
+
+                self.remaining = val & 0x7F;
+                self.state = if val & 0x80 != 0 {
+                    HdmaState::HBlank
+                } else {
+                    HdmaState::General
+                };
+                return self.state == HdmaState::General;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Whether an HBlank transfer is waiting on the next HBlank.
+    #[inline]
+    pub fn hblank_pending(&self) -> bool {
+        self.state == HdmaState::HBlank
+    }
+
+    /// Returns the source address and VRAM offset of the next block and moves past it, or
+    /// `None` when nothing is being transferred.
+    #[inline]
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.state == HdmaState::Idle {
+            return None;
+        }
+
+        let block = (self.source, self.dest);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.dest = (self.dest + HDMA_BLOCK_SIZE) & 0x1FF0;
+
+        if self.remaining == 0 {
+            self.state = HdmaState::Idle;
+            self.remaining = 0x7F;
+        } else {
+            self.remaining -= 1;
+        }
+
+        Some(block)
+    }
+
+    /// T-cycles the CPU is stalled for each block. A block takes the same wall time at either
+    /// speed, which is twice the CPU cycles in double speed mode.
+    #[inline]
+    pub fn block_cycles(double_speed: bool) -> u32 {
+        if double_speed {
+            64
+        } else {
+            32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn general_transfer_counts_down() {
+        let mut hdma = Hdma::new();
+        hdma.write(0xff51, 0xC1);
+        hdma.write(0xff52, 0x2F);
+        hdma.write(0xff53, 0xFF);
+        hdma.write(0xff54, 0xFF);
+        assert!(hdma.write(0xff55, 0x01));
+
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x1FF0)));
+        assert_eq!(hdma.read(0xff55), 0x00);
+        assert_eq!(hdma.next_block(), Some((0xC130, 0x0000)));
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.read(0xff55), 0xFF);
+    }
+
+    #[test]
+    fn hblank_transfer_can_be_cancelled() {
+        let mut hdma = Hdma::new();
+        assert!(!hdma.write(0xff55, 0x83));
+        assert!(hdma.hblank_pending());
+        hdma.next_block();
+        assert_eq!(hdma.read(0xff55), 0x02);
+
+        assert!(!hdma.write(0xff55, 0x00));
+        assert!(!hdma.hblank_pending());
+        assert_eq!(hdma.read(0xff55), 0x82);
+    }
+}
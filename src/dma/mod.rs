@@ -0,0 +1,7 @@
+// Everything that copies memory behind the CPU's back
+
+mod hdma;
+mod oam;
+
+pub use hdma::*;
+pub use oam::*;
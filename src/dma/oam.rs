@@ -0,0 +1,158 @@
+/// Bytes in OAM, and thus M-cycles in an OAM DMA transfer.
+pub const OAM_DMA_LENGTH: u8 = 0xA0;
+
+/// How faithfully OAM DMA is emulated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OamDmaMode {
+    /// Copies all 160 bytes the moment 0xFF46 is written. Cheap, and good enough for any game that
+    /// waits out the transfer in HRAM like it's supposed to.
+    Instant,
+    /// Moves one byte per M-cycle after a one M-cycle startup delay, and locks the CPU out of
+    /// everything but HRAM while doing so.
+    Accurate,
+}
+
+/// The OAM DMA controller behind 0xFF46.
+pub struct OamDma {
+    mode: OamDmaMode,
+    /// Address of the first byte to copy
+    source: u16,
+    /// Bytes copied so far, `None` while idle
+    progress: Option<u8>,
+    /// M-cycles left before the first byte moves
+    delay: u8,
+    /// T-cycles that haven't added up to a full M-cycle yet
+    remainder: u32,
+    /// The byte most recently driven onto the bus
+    current: u8,
+}
+
+impl OamDma {
+    pub fn new(mode: OamDmaMode) -> Self {
+        Self {
+            mode,
+            source: 0,
+            progress: None,
+            delay: 0,
+            remainder: 0,
+            current: 0xFF,
+        }
+    }
+
+    #[inline]
+    pub fn mode(&self) -> OamDmaMode {
+        self.mode
+    }
+
+    #[inline]
+    pub fn set_mode(&mut self, mode: OamDmaMode) {
+        self.mode = mode;
+    }
+
+    /// Returns the address the transfer reads from for a write of `page` to 0xFF46. Pages past
+    /// WRAM read from its echo.
+    #[inline]
+    pub fn source_of(page: u8) -> u16 {
+        let source = (page as u16) << 8;
+        if source >= 0xE000 {
+            source - 0x2000
+        } else {
+            source
+        }
+    }
+
+    /// Kicks off (or restarts) an accurate transfer from `page`.
+    #[inline]
+    pub fn start(&mut self, page: u8) {
+        self.source = Self::source_of(page);
+        self.progress = Some(0);
+        self.delay = 1;
+        self.remainder = 0;
+    }
+
+    /// Whether a transfer currently owns the bus.
+    #[inline]
+    pub fn active(&self) -> bool {
+        self.progress.is_some() && self.delay == 0
+    }
+
+    /// Whether the CPU is locked out of `addr`. Only HRAM stays reachable during a transfer.
+    #[inline]
+    pub fn blocks(&self, addr: u16) -> bool {
+        self.active() && !(0xff80..=0xfffe).contains(&addr)
+    }
+
+    /// What the CPU reads from a blocked address: whatever the transfer has on the bus.
+    #[inline]
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    /// Advances the controller by `cycles` T-cycles and returns how many bytes are due.
+    #[inline]
+    pub fn tick(&mut self, cycles: u32) -> u32 {
+        let progress = match self.progress {
+            Some(p) => p,
+            None => return 0,
+        };
+
+        self.remainder += cycles;
+        let mut m = self.remainder / 4;
+        self.remainder %= 4;
+
+        let wait = m.min(self.delay as u32);
+        self.delay -= wait as u8;
+        m -= wait;
+
+        m.min((OAM_DMA_LENGTH - progress) as u32)
+    }
+
+    /// Returns the source address and OAM index of the next byte to move.
+    #[inline]
+    pub fn next(&self) -> (u16, usize) {
+        let i = self.progress.unwrap_or(0);
+        (self.source + i as u16, i as usize)
+    }
+
+    /// Records that `val` was just copied into OAM.
+    #[inline]
+    pub fn transferred(&mut self, val: u8) {
+        self.current = val;
+        self.progress = match self.progress {
+            Some(p) if p + 1 < OAM_DMA_LENGTH => Some(p + 1),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_takes_161_m_cycles() {
+        let mut dma = OamDma::new(OamDmaMode::Accurate);
+        dma.start(0xC1);
+        assert_eq!(dma.next(), (0xC100, 0));
+        assert!(!dma.active());
+
+        let mut moved = dma.tick(4);
+        assert_eq!(moved, 0);
+        assert!(dma.blocks(0xC000));
+        assert!(!dma.blocks(0xFF80));
+
+        for _ in 0..160 {
+            moved += dma.tick(4);
+            dma.transferred(0x42);
+        }
+        assert_eq!(moved, 160);
+        assert!(!dma.active());
+        assert_eq!(dma.tick(4), 0);
+    }
+
+    #[test]
+    fn echo_pages_read_wram() {
+        assert_eq!(OamDma::source_of(0xFE), 0xDE00);
+        assert_eq!(OamDma::source_of(0x80), 0x8000);
+    }
+}
@@ -0,0 +1,270 @@
+// Headless test ROM runner, host only
+#![cfg(test)]
+
+extern crate std;
+
+use std::boxed::Box;
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
+
+use crate::cpu::{EmuError, CPU};
+use crate::serial::LinkTransport;
+
+/// About a minute of emulated time, the slowest Blargg ROMs need around half of that.
+pub const DEFAULT_TIMEOUT: u64 = 4_194_304 * 60;
+
+/// What Mooneye's tests leave in B, C, D, E, H and L before hitting `LD B,B` on success.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// ...and in all six on failure.
+const MOONEYE_FAIL: u8 = 0x42;
+const LD_B_B: u8 = 0x40;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// Ran out of cycles before the ROM said anything conclusive
+    Timeout,
+    /// The CPU hit something it can't carry on from
+    Error(EmuError),
+    /// The emulator panicked, with the panic message
+    Crashed(String),
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub outcome: Outcome,
+    /// Everything the ROM wrote to the serial port
+    pub serial: String,
+    /// T-cycles emulated
+    pub cycles: u64,
+}
+
+/// Blargg's ROMs print their results over serial, this collects them.
+struct Capture(Vec<u8>);
+
+impl LinkTransport for Capture {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.0.push(byte);
+        0xFF
+    }
+
+    fn poll_slave(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+fn blargg(serial: &[u8]) -> Option<Outcome> {
+    let contains = |needle: &[u8]| serial.windows(needle.len()).any(|w| w == needle);
+    if contains(b"Passed") {
+        Some(Outcome::Passed)
+    } else if contains(b"Failed") {
+        Some(Outcome::Failed)
+    } else {
+        None
+    }
+}
+
+fn mooneye(registers: [u8; 8]) -> Option<Outcome> {
+    let bcdehl = &registers[2..];
+    if bcdehl == MOONEYE_PASS {
+        Some(Outcome::Passed)
+    } else if bcdehl.iter().all(|r| *r == MOONEYE_FAIL) {
+        Some(Outcome::Failed)
+    } else {
+        None
+    }
+}
+
+/// Boots `rom` without a screen and runs it until it reports a result, crashes, or `timeout`
+/// T-cycles pass.
+pub fn run(rom: &'static [u8], timeout: u64) -> Report {
+    let mut capture = Capture(Vec::new());
+    let mut cycles = 0u64;
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut cpu = Box::new(CPU::new(true));
+        cpu.load_rom(rom);
+
+        let mut printed = 0;
+        while cycles < timeout {
+            let opcode = cpu.peek(cpu.pc());
+            match cpu.execute() {
+                Ok(ran) => cycles += ran as u64,
+                Err(err) => return Outcome::Error(err),
+            }
+            cpu.service_link(&mut capture);
+
+            if capture.0.len() != printed {
+                printed = capture.0.len();
+                if let Some(outcome) = blargg(&capture.0) {
+                    return outcome;
+                }
+            }
+            if opcode == LD_B_B {
+                if let Some(outcome) = mooneye(cpu.registers()) {
+                    return outcome;
+                }
+            }
+        }
+        Outcome::Timeout
+    }));
+
+    let outcome = result.unwrap_or_else(|panic| {
+        let message = if let Some(s) = panic.downcast_ref::<&str>() {
+            String::from(*s)
+        } else if let Some(s) = panic.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            String::from("unknown panic")
+        };
+        Outcome::Crashed(message)
+    });
+
+    Report {
+        outcome,
+        serial: String::from_utf8_lossy(&capture.0).into_owned(),
+        cycles,
+    }
+}
+
+/// Test ROMs live in `$GBC_TEST_ROMS`, or test-roms/ next to Cargo.toml. They aren't ours to
+/// redistribute, so bring your own.
+fn rom_dir() -> PathBuf {
+    std::env::var_os("GBC_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("test-roms"))
+}
+
+fn roms_in(dir: &Path) -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "gb" || ext == "gbc"))
+                .collect()
+        })
+        .unwrap_or_default();
+    roms.sort();
+    roms
+}
+
+/// Runs every ROM of a suite, preferring the individual ROMs over the combined one. A suite
+/// that isn't on disk fails, these only run with `--ignored` so it's asked for.
+fn suite(name: &str) {
+    let dir = rom_dir().join(name);
+    let mut roms = roms_in(&dir.join("individual"));
+    if roms.is_empty() {
+        roms = roms_in(&dir);
+    }
+    assert!(!roms.is_empty(), "no {} ROMs in {}", name, dir.display());
+
+    let mut failures = Vec::new();
+    for path in roms {
+        let rom: &'static [u8] = Box::leak(std::fs::read(&path).unwrap().into_boxed_slice());
+        let report = run(rom, DEFAULT_TIMEOUT);
+        if report.outcome != Outcome::Passed {
+            failures.push(std::format!(
+                "{}: {:?} after {} cycles\n{}",
+                path.display(),
+                report.outcome,
+                report.cycles,
+                report.serial
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see rom_dir"]
+fn cpu_instrs() {
+    suite("cpu_instrs");
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see rom_dir"]
+fn instr_timing() {
+    suite("instr_timing");
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see rom_dir"]
+fn mem_timing() {
+    suite("mem_timing");
+}
+
+#[test]
+fn detects_results() {
+    assert_eq!(
+        blargg(b"cpu_instrs\n\nPassed all tests\n"),
+        Some(Outcome::Passed)
+    );
+    assert_eq!(
+        blargg(b"01:ok  02:01  \n\nFailed 1 tests"),
+        Some(Outcome::Failed)
+    );
+    assert_eq!(blargg(b"cpu_instrs\n\n01:ok"), None);
+
+    assert_eq!(mooneye([0, 0, 3, 5, 8, 13, 21, 34]), Some(Outcome::Passed));
+    assert_eq!(
+        mooneye([0, 0, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42]),
+        Some(Outcome::Failed)
+    );
+    assert_eq!(mooneye([0; 8]), None);
+}
+
+#[test]
+fn boots_rom_and_reads_serial() {
+    let mut rom = std::vec![0u8; 0x8000];
+    let mut pc = 0x100;
+    for c in b"Passed" {
+        // LD A,c / LDH (SB),A / LD A,0x81 / LDH (SC),A
+        // wait: LDH A,(SC) / BIT 7,A / JR NZ,wait
+        let code = [
+            0x3E, *c, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA,
+        ];
+        rom[pc..pc + code.len()].copy_from_slice(&code);
+        pc += code.len();
+    }
+
+    let report = run(Box::leak(rom.into_boxed_slice()), DEFAULT_TIMEOUT);
+    assert_eq!(report.outcome, Outcome::Passed, "{}", report.serial);
+    assert_eq!(report.serial, "Passed");
+}
+
+/// T-cycles `execute` reports between LY moving on and moving on again.
+pub(crate) fn cycles_per_line(cpu: &mut CPU) -> u32 {
+    let wait = |cpu: &mut CPU| {
+        let ly = cpu.peek(0xff44);
+        let mut cycles = 0;
+        while cpu.peek(0xff44) == ly {
+            cycles += cpu.execute().unwrap();
+        }
+        cycles
+    };
+    wait(cpu);
+    wait(cpu)
+}
+
+/// A ROM with `code` at the entry point, for tests that just want to run a few instructions.
+pub(crate) fn program(code: &[u8]) -> &'static [u8] {
+    let mut rom = std::vec![0u8; 0x8000];
+    rom[0x100..0x100 + code.len()].copy_from_slice(code);
+    Box::leak(rom.into_boxed_slice())
+}
+
+#[test]
+fn reports_lockups() {
+    let report = run(program(&[0x00, 0xFD]), DEFAULT_TIMEOUT);
+    assert_eq!(
+        report.outcome,
+        Outcome::Error(EmuError::IllegalOpcode {
+            pc: 0x101,
+            opcode: 0xFD
+        })
+    );
+    assert_eq!(report.cycles, 4);
+}
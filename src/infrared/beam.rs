@@ -0,0 +1,118 @@
+use core::cell::RefCell;
+
+use super::IrTransport;
+
+/// LED switches remembered per end. Enough for the far end to be a few pulses behind.
+const HISTORY: usize = 16;
+
+/// When one end's LED went on and off, oldest first.
+struct History {
+    edges: [(u64, bool); HISTORY],
+    len: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            edges: [(0, false); HISTORY],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, at: u64, on: bool) {
+        if self.len == HISTORY {
+            self.edges.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.edges[self.len] = (at, on);
+        self.len += 1;
+    }
+
+    /// Whether the LED was on at `at`.
+    fn lit(&self, at: u64) -> bool {
+        let edges = &self.edges[..self.len];
+        match edges.iter().rev().find(|(when, _)| *when <= at) {
+            Some((_, on)) => *on,
+            // Before anything we remember, so it was the other way around from the first switch
+            None => edges.first().is_some_and(|(_, on)| !on),
+        }
+    }
+}
+
+/// Two Game Boys facing each other in the same process. Each end keeps its own clock, so light
+/// is seen when it was on at the receiver's point in emulated time, not whenever the sender
+/// happened to be stepped.
+pub struct Beam {
+    leds: [RefCell<History>; 2],
+}
+
+impl Beam {
+    pub const fn new() -> Self {
+        Self {
+            leds: [RefCell::new(History::new()), RefCell::new(History::new())],
+        }
+    }
+
+    /// Returns both ends.
+    pub fn ends(&self) -> (BeamEnd<'_>, BeamEnd<'_>) {
+        (
+            BeamEnd {
+                beam: self,
+                side: 0,
+            },
+            BeamEnd {
+                beam: self,
+                side: 1,
+            },
+        )
+    }
+}
+
+/// One end of a `Beam`.
+pub struct BeamEnd<'b> {
+    beam: &'b Beam,
+    side: usize,
+}
+
+impl<'b> IrTransport for BeamEnd<'b> {
+    fn led(&mut self, on: bool, at: u64) {
+        self.beam.leds[self.side].borrow_mut().push(at, on);
+    }
+
+    fn sensing(&mut self, at: u64) -> bool {
+        self.beam.leds[1 - self.side].borrow().lit(at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses_line_up_in_emulated_time() {
+        let beam = Beam::new();
+        let (mut a, mut b) = beam.ends();
+
+        // a runs ahead and sends a pulse from 100 to 200
+        a.led(true, 100);
+        a.led(false, 200);
+
+        // b catches up later and still sees it at the right time
+        assert!(!b.sensing(50));
+        assert!(b.sensing(100));
+        assert!(b.sensing(199));
+        assert!(!b.sensing(200));
+    }
+
+    #[test]
+    fn forgets_old_pulses() {
+        let mut history = History::new();
+        for i in 0..HISTORY as u64 + 2 {
+            history.push(i * 10, i % 2 == 0);
+        }
+        assert!(history.lit(165));
+        assert!(!history.lit(175));
+        // Out of the window, so going by the oldest switch we still have
+        assert!(!history.lit(0));
+    }
+}
@@ -0,0 +1,219 @@
+// CGB infrared port and whatever's on the other side of it
+
+mod beam;
+
+pub use beam::*;
+
+const RP_LED: u8 = 0b0000_0001;
+/// Reads 0 while light is hitting the sensor
+const RP_NO_LIGHT: u8 = 0b0000_0010;
+const RP_READ_ENABLE: u8 = 0b1100_0000;
+const RP_UNUSED: u8 = 0b0011_1100;
+
+/// Whatever the LED shines at and the sensor looks at. Times are in the caller's own T-cycles,
+/// which lets two ends that don't run in lockstep still line pulses up.
+pub trait IrTransport {
+    /// Our LED was switched on or off at `at`.
+    fn led(&mut self, on: bool, at: u64);
+
+    /// Whether light from the other end reaches the sensor at `at`.
+    fn sensing(&mut self, at: u64) -> bool;
+}
+
+/// Nobody's pointing a Game Boy at us.
+pub struct NoIr;
+
+impl IrTransport for NoIr {
+    fn led(&mut self, _on: bool, _at: u64) {}
+
+    fn sensing(&mut self, _at: u64) -> bool {
+        false
+    }
+}
+
+/// RP (0xFF56), CGB only.
+///
+/// The LED and sensor are only synced with the transport in `service`, so anything that counts
+/// pulses in a tight loop sees them a whole instruction late at worst. That's well under the
+/// length of the pulses games send.
+pub struct Infrared {
+    cgb: bool,
+    rp: u8,
+    /// T-cycles since power on
+    now: u64,
+    /// An LED switch the transport hasn't heard about yet
+    switched: Option<(bool, u64)>,
+    sensing: bool,
+}
+
+impl Infrared {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            rp: 0,
+            now: 0,
+            switched: None,
+            sensing: false,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+    }
+
+    /// Tells `ir` about the LED and asks it whether there's light on the sensor.
+    pub fn service<T: IrTransport + ?Sized>(&mut self, ir: &mut T) {
+        if let Some((on, at)) = self.switched.take() {
+            ir.led(on, at);
+        }
+        self.sensing = ir.sensing(self.now);
+    }
+
+    #[inline]
+    pub fn read(&self) -> u8 {
+        if !self.cgb {
+            return 0xFF;
+        }
+        let light = self.rp & RP_READ_ENABLE == RP_READ_ENABLE && self.sensing;
+        RP_UNUSED | self.rp | if light { 0 } else { RP_NO_LIGHT }
+    }
+
+    #[inline]
+    pub fn write(&mut self, val: u8) {
+        if !self.cgb {
+            return;
+        }
+        let was = self.rp & RP_LED != 0;
+        self.rp = val & (RP_LED | RP_READ_ENABLE);
+        let on = self.rp & RP_LED != 0;
+        if on != was {
+            self.switched = Some((on, self.now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::harness::program;
+
+    /// T-cycles between `send`'s two RP writes: LD B / 99 DEC B, JR NZ taken / DEC B, JR NZ not
+    /// taken / LD A / LDH
+    const PULSE_CYCLES: u32 = 8 + 99 * 16 + 12 + 8 + 12;
+
+    /// A CPU that lights its LED for `PULSE_CYCLES` and then sits in a loop, switching to double
+    /// speed first if asked.
+    fn sender(double_speed: bool) -> Box<CPU> {
+        let mut code = Vec::new();
+        if double_speed {
+            // LD A,0x01 / LDH (KEY1),A / STOP
+            code.extend_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00]);
+        }
+        // LD A,0x01 / LDH (RP),A / LD B,100 / loop: DEC B / JR NZ,loop / LD A,0x00 / LDH (RP),A
+        // / JR -2
+        code.extend_from_slice(&[
+            0x3E, 0x01, 0xE0, 0x56, 0x06, 100, 0x05, 0x20, 0xFD, 0x3E, 0x00, 0xE0, 0x56, 0x18, 0xFE,
+        ]);
+        let mut cpu = Box::new(CPU::new(true));
+        cpu.load_rom(program(&code));
+        cpu
+    }
+
+    /// Runs a CPU that only listens and returns the emulated times light came on and went off,
+    /// as far as a 12 T-cycle loop can tell.
+    fn receive(end: &mut BeamEnd) -> (u32, u32) {
+        // LD A,0xC0 / LDH (RP),A / loop: JR loop
+        let mut cpu = Box::new(CPU::new(true));
+        cpu.load_rom(program(&[0x3E, 0xC0, 0xE0, 0x56, 0x18, 0xFE]));
+        let mut now = 0;
+        let mut on = None;
+        for _ in 0..1000 {
+            now += cpu.execute().unwrap();
+            cpu.service_ir(end);
+            let lit = cpu.peek(0xff56) & RP_NO_LIGHT == 0;
+            match on {
+                None if lit => on = Some(now),
+                Some(on) if !lit => return (on, now),
+                _ => {}
+            }
+        }
+        panic!("never saw a whole pulse");
+    }
+
+    #[test]
+    fn two_cpus_pulse_each_other() {
+        for double_speed in [false, true] {
+            let beam = Beam::new();
+            let (mut a_end, mut b_end) = beam.ends();
+
+            // The sender runs all the way ahead, the receiver still sees it at the right time
+            let mut a = sender(double_speed);
+            for _ in 0..1000 {
+                a.execute().unwrap();
+                a.service_ir(&mut a_end);
+            }
+            let (on, off) = receive(&mut b_end);
+
+            // The LED and everything else on a fixed clock count half as many cycles when
+            // the CPU's running twice as fast
+            let pulse = if double_speed {
+                PULSE_CYCLES / 2
+            } else {
+                PULSE_CYCLES
+            };
+            assert!(
+                (pulse - 12..=pulse + 12).contains(&(off - on)),
+                "{} cycles in double speed {}",
+                off - on,
+                double_speed
+            );
+            if !double_speed {
+                // Switched on at the end of the first LDH
+                assert!((20..=20 + 12).contains(&on), "on at {}", on);
+            }
+        }
+    }
+
+    #[test]
+    fn sensor_needs_read_enable() {
+        let beam = Beam::new();
+        let (mut a_end, mut b_end) = beam.ends();
+        let mut a = Infrared::new(true);
+        let mut b = Infrared::new(true);
+
+        a.write(RP_LED);
+        a.service(&mut a_end);
+        b.tick(4);
+        b.service(&mut b_end);
+        assert_eq!(b.read(), 0x3E);
+
+        b.write(RP_READ_ENABLE);
+        b.service(&mut b_end);
+        assert_eq!(b.read(), 0xFC);
+    }
+
+    #[test]
+    fn own_led_isnt_seen() {
+        let beam = Beam::new();
+        let (mut a_end, _) = beam.ends();
+        let mut a = Infrared::new(true);
+        a.write(RP_READ_ENABLE | RP_LED);
+        a.tick(4);
+        a.service(&mut a_end);
+        assert_eq!(a.read(), 0xFF);
+    }
+
+    #[test]
+    fn dmg_has_no_port() {
+        let mut ir = Infrared::new(false);
+        ir.write(0xC1);
+        assert_eq!(ir.read(), 0xFF);
+    }
+}
@@ -0,0 +1,286 @@
+use super::input::*;
+use super::joypad::{self, A, B, SELECT, START};
+
+/// Polls the Game Boy button a menu tap turns into stays held, long enough for a game polling
+/// once a frame to notice.
+const TAP_POLLS: u8 = 8;
+/// Bytes `ChordSettings::encode` writes.
+pub const CHORD_SETTINGS_SIZE: usize = 8;
+
+/// How the board's seven buttons stand in for the Game Boy's eight. Durations are counted in
+/// polls, which the firmware does every 7ms or so.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChordSettings {
+    /// Game Boy buttons pressed by tapping menu on its own
+    pub menu_tap: u8,
+    /// Game Boy buttons menu+A stands for, 0 leaves A alone
+    pub menu_a: u8,
+    /// Game Boy buttons menu+B stands for, 0 leaves B alone
+    pub menu_b: u8,
+    /// Holding menu on its own this long opens the emulator menu instead
+    pub long_press: u16,
+    /// A button has to read the same for this many polls before we believe it
+    pub debounce: u8,
+    pub turbo_a: bool,
+    pub turbo_b: bool,
+    /// Polls spent pressed, and then released, by turbo
+    pub turbo_period: u8,
+}
+
+impl Default for ChordSettings {
+    fn default() -> Self {
+        Self {
+            menu_tap: START,
+            menu_a: 0,
+            menu_b: SELECT,
+            long_press: 70,
+            debounce: 3,
+            turbo_a: false,
+            turbo_b: false,
+            turbo_period: 4,
+        }
+    }
+}
+
+impl ChordSettings {
+    pub fn encode(&self, out: &mut [u8]) {
+        out[0] = self.menu_tap;
+        out[1] = self.menu_a;
+        out[2] = self.menu_b;
+        out[3..5].copy_from_slice(&self.long_press.to_le_bytes());
+        out[5] = self.debounce;
+        out[6] = (self.turbo_a as u8) | ((self.turbo_b as u8) << 1);
+        out[7] = self.turbo_period;
+    }
+
+    pub fn decode(bytes: &[u8]) -> Self {
+        Self {
+            menu_tap: bytes[0],
+            menu_a: bytes[1],
+            menu_b: bytes[2],
+            long_press: u16::from_le_bytes([bytes[3], bytes[4]]),
+            debounce: bytes[5],
+            turbo_a: bytes[6] & 0x01 != 0,
+            turbo_b: bytes[6] & 0x02 != 0,
+            turbo_period: bytes[7].max(1),
+        }
+    }
+}
+
+/// Turns raw button samples into Game Boy buttons: debounces them, resolves menu chords and
+/// long presses, and pulses A/B for turbo.
+pub struct Chords {
+    settings: ChordSettings,
+    /// Debounced buttons
+    stable: u8,
+    /// Consecutive polls each button has disagreed with `stable`
+    counters: [u8; 8],
+    /// Polls menu has been held for
+    menu_polls: u16,
+    /// Menu got used for something this hold, so letting go of it isn't a tap
+    menu_used: bool,
+    /// Polls left on the current menu tap
+    tap_polls: u8,
+    /// Polls into the current turbo cycle, which is two periods long and so can outgrow a u8
+    turbo_phase: u16,
+    menu_requested: bool,
+}
+
+impl Chords {
+    pub fn new(settings: ChordSettings) -> Self {
+        Self {
+            settings,
+            stable: 0,
+            counters: [0; 8],
+            menu_polls: 0,
+            menu_used: false,
+            tap_polls: 0,
+            turbo_phase: 0,
+            menu_requested: false,
+        }
+    }
+
+    #[inline]
+    pub fn settings(&self) -> &ChordSettings {
+        &self.settings
+    }
+
+    #[inline]
+    pub fn set_settings(&mut self, settings: ChordSettings) {
+        self.settings = settings;
+    }
+
+    /// The debounced `BTN_*` bits as of the last `update`.
+    #[inline]
+    pub fn held(&self) -> u8 {
+        self.stable
+    }
+
+    /// Returns true once per long press of menu.
+    #[inline]
+    pub fn menu_requested(&mut self) -> bool {
+        let requested = self.menu_requested;
+        self.menu_requested = false;
+        requested
+    }
+
+    fn debounce(&mut self, raw: u8) -> u8 {
+        for bit in 0..8 {
+            let mask = 1 << bit;
+            if (raw ^ self.stable) & mask == 0 {
+                self.counters[bit] = 0;
+                continue;
+            }
+            self.counters[bit] += 1;
+            if self.counters[bit] >= self.settings.debounce {
+                self.stable ^= mask;
+                self.counters[bit] = 0;
+            }
+        }
+        self.stable
+    }
+
+    /// Feeds in one sample of `BTN_*` bits and returns the Game Boy buttons to hold, see
+    /// io/joypad.rs.
+    pub fn update(&mut self, raw: u8) -> u8 {
+        let held = self.debounce(raw);
+        let mut out = joypad::from_buttons(held);
+
+        if held & BTN_MENU != 0 {
+            self.menu_polls = self.menu_polls.saturating_add(1);
+
+            if held & BTN_A != 0 && self.settings.menu_a != 0 {
+                out = (out & !A) | self.settings.menu_a;
+            }
+            if held & BTN_B != 0 && self.settings.menu_b != 0 {
+                out = (out & !B) | self.settings.menu_b;
+            }
+            if held & !BTN_MENU != 0 {
+                self.menu_used = true;
+            }
+
+            if !self.menu_used && self.menu_polls >= self.settings.long_press {
+                self.menu_requested = true;
+                self.menu_used = true;
+            }
+        } else {
+            if self.menu_polls > 0 && !self.menu_used {
+                self.tap_polls = TAP_POLLS;
+            }
+            self.menu_polls = 0;
+            self.menu_used = false;
+        }
+
+        if self.tap_polls > 0 {
+            self.tap_polls -= 1;
+            out |= self.settings.menu_tap;
+        }
+
+        let period = u16::from(self.settings.turbo_period.max(1));
+        self.turbo_phase = (self.turbo_phase + 1) % (period * 2);
+        if self.turbo_phase >= period {
+            if self.settings.turbo_a && held & BTN_A != 0 {
+                out &= !A;
+            }
+            if self.settings.turbo_b && held & BTN_B != 0 {
+                out &= !B;
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chords() -> Chords {
+        Chords::new(ChordSettings {
+            debounce: 1,
+            long_press: 10,
+            ..ChordSettings::default()
+        })
+    }
+
+    #[test]
+    fn debounce_ignores_glitches() {
+        let mut chords = Chords::new(ChordSettings::default());
+        assert_eq!(chords.update(BTN_A), 0);
+        assert_eq!(chords.update(0), 0);
+        chords.update(BTN_A);
+        chords.update(BTN_A);
+        assert_eq!(chords.update(BTN_A), A);
+    }
+
+    #[test]
+    fn menu_tap_is_start() {
+        let mut chords = chords();
+        assert_eq!(chords.update(BTN_MENU), 0);
+        assert_eq!(chords.update(0), START);
+        for _ in 1..TAP_POLLS {
+            assert_eq!(chords.update(0), START);
+        }
+        assert_eq!(chords.update(0), 0);
+    }
+
+    #[test]
+    fn menu_b_is_select_and_not_a_tap() {
+        let mut chords = chords();
+        chords.update(BTN_MENU);
+        assert_eq!(chords.update(BTN_MENU | BTN_B), SELECT);
+        assert_eq!(chords.update(0), 0);
+    }
+
+    #[test]
+    fn long_press_opens_menu() {
+        let mut chords = chords();
+        for _ in 0..10 {
+            chords.update(BTN_MENU);
+        }
+        assert!(chords.menu_requested());
+        assert!(!chords.menu_requested());
+        assert_eq!(chords.update(0), 0);
+    }
+
+    #[test]
+    fn turbo_pulses_a() {
+        let mut chords = Chords::new(ChordSettings {
+            debounce: 1,
+            turbo_a: true,
+            turbo_period: 2,
+            ..ChordSettings::default()
+        });
+        let pulses: [u8; 4] = core::array::from_fn(|_| chords.update(BTN_A));
+        assert_eq!(pulses, [A, 0, 0, A]);
+    }
+
+    #[test]
+    fn long_turbo_periods_from_flash() {
+        for period in [0x80, 0xFF] {
+            let mut bytes = [0u8; CHORD_SETTINGS_SIZE];
+            ChordSettings::default().encode(&mut bytes);
+            bytes[5] = 1;
+            bytes[6] = 0x01;
+            bytes[7] = period;
+            let mut chords = Chords::new(ChordSettings::decode(&bytes));
+            let held = (0..period as usize * 2)
+                .filter(|_| chords.update(BTN_A) & A != 0)
+                .count();
+            assert_eq!(held, period as usize);
+        }
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = ChordSettings {
+            menu_a: START,
+            turbo_b: true,
+            long_press: 300,
+            ..ChordSettings::default()
+        };
+        let mut bytes = [0u8; CHORD_SETTINGS_SIZE];
+        settings.encode(&mut bytes);
+        assert_eq!(ChordSettings::decode(&bytes), settings);
+    }
+}
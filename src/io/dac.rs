@@ -0,0 +1,216 @@
+use bsp::hal::clock::{ClockGenId, GenericClockController};
+use bsp::hal::gpio::v2::{AlternateB, Pin, PA02};
+use bsp::hal::pac::{DAC, DMAC, MCLK, TC3};
+use bsp::hal::prelude::*;
+use bsp::hal::timer::TimerCounter3;
+use core::ptr;
+use itsybitsy_m4 as bsp;
+
+use crate::apu::{AudioSink, DoubleBuffer};
+
+/// Output rate, which the mixer should be set to as well.
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// Samples per half, about 23ms. The main loop has to come back within that.
+const HALF: usize = 512;
+
+/// Samples queued in front of the halves, a couple of frames' worth.
+const QUEUE: usize = 2048;
+
+pub type AudioBuffer = DoubleBuffer<HALF, QUEUE>;
+
+/// DMAC channel the DAC gets, the only one in use.
+const CHANNEL: usize = 0;
+
+// Transfer descriptor BTCTRL bits
+const BTCTRL_VALID: u16 = 1 << 0;
+const BTCTRL_BEATSIZE_HWORD: u16 = 1 << 8;
+const BTCTRL_SRCINC: u16 = 1 << 10;
+
+/// A DMAC transfer descriptor, laid out the way the controller reads it.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Descriptor {
+    btctrl: u16,
+    btcnt: u16,
+    srcaddr: u32,
+    dstaddr: u32,
+    descaddr: u32,
+}
+
+impl Descriptor {
+    const EMPTY: Self = Self {
+        btctrl: 0,
+        btcnt: 0,
+        srcaddr: 0,
+        dstaddr: 0,
+        descaddr: 0,
+    };
+}
+
+// Channel 0's first descriptor has to sit at BASEADDR and its write-back at WRBADDR. The second
+// half's descriptor can go anywhere, the first links to it and it links back.
+static mut FIRST: Descriptor = Descriptor::EMPTY;
+static mut SECOND: Descriptor = Descriptor::EMPTY;
+static mut WRITE_BACK: Descriptor = Descriptor::EMPTY;
+
+/// The DAC on A0 playing a `DoubleBuffer`. TC3 overflows at the sample rate and each overflow
+/// has the DMAC move one code into the DAC, looping over both halves forever without the CPU.
+/// `write` is where the half the DMAC just finished gets refilled, so audio has to be drained
+/// into it regularly, silence or not.
+pub struct Dac {
+    buffer: &'static mut AudioBuffer,
+    dac: DAC,
+    dmac: DMAC,
+    _timer: TimerCounter3,
+    _pin: Pin<PA02, AlternateB>,
+}
+
+impl Dac {
+    pub fn new(
+        clocks: &mut GenericClockController,
+        mclk: &mut MCLK,
+        dac: DAC,
+        dmac: DMAC,
+        tc3: TC3,
+        pin: impl Into<Pin<PA02, AlternateB>>,
+        buffer: &'static mut AudioBuffer,
+    ) -> Self {
+        // The DAC wants 12MHz at most, and GCLK5 is already running at 2MHz
+        let gclk5 = clocks.get_gclk(ClockGenId::GCLK5).unwrap();
+        clocks.dac(&gclk5).unwrap();
+        mclk.apbdmask.modify(|_, w| w.dac_().set_bit());
+
+        dac.ctrla.write(|w| w.swrst().set_bit());
+        while dac.syncbusy.read().swrst().bit_is_set() {}
+        dac.ctrlb.write(|w| w.refsel().vddana());
+        dac.dacctrl[0].write(|w| {
+            w.enable().set_bit();
+            w.leftadj().set_bit();
+            w.cctrl().cc12m()
+        });
+        dac.ctrla.write(|w| w.enable().set_bit());
+        while dac.syncbusy.read().enable().bit_is_set() {}
+        while dac.status.read().ready0().bit_is_clear() {}
+        dac.data[0].write(|w| unsafe { w.data().bits(buffer.half(0)[0]) });
+
+        // Both halves, one code per trigger, ending up at the DAC's data register. Sources point
+        // one past the end when they increment
+        let dstaddr = dac.data[0].as_ptr() as u32;
+        let halves = [buffer.half(0).as_ptr_range(), buffer.half(1).as_ptr_range()];
+        let btctrl = BTCTRL_VALID | BTCTRL_BEATSIZE_HWORD | BTCTRL_SRCINC;
+        unsafe {
+            FIRST = Descriptor {
+                btctrl,
+                btcnt: HALF as u16,
+                srcaddr: halves[0].end as u32,
+                dstaddr,
+                descaddr: ptr::addr_of!(SECOND) as u32,
+            };
+            SECOND = Descriptor {
+                btctrl,
+                btcnt: HALF as u16,
+                srcaddr: halves[1].end as u32,
+                dstaddr,
+                descaddr: ptr::addr_of!(FIRST) as u32,
+            };
+        }
+
+        mclk.ahbmask.modify(|_, w| w.dmac_().set_bit());
+        dmac.ctrl.write(|w| w.swrst().set_bit());
+        while dmac.ctrl.read().swrst().bit_is_set() {}
+        unsafe {
+            dmac.baseaddr
+                .write(|w| w.baseaddr().bits(ptr::addr_of!(FIRST) as u32));
+            dmac.wrbaddr
+                .write(|w| w.wrbaddr().bits(ptr::addr_of!(WRITE_BACK) as u32));
+        }
+        dmac.ctrl.write(|w| {
+            w.dmaenable().set_bit();
+            w.lvlen0().set_bit();
+            w.lvlen1().set_bit();
+            w.lvlen2().set_bit();
+            w.lvlen3().set_bit()
+        });
+        dmac.channel[CHANNEL].chctrla.write(|w| {
+            w.trigsrc().tc3_ovf();
+            w.trigact().burst();
+            w.enable().set_bit()
+        });
+
+        let gclk0 = clocks.gclk0();
+        let tc23 = clocks.tc2_tc3(&gclk0).unwrap();
+        let mut timer = TimerCounter3::tc3_(&tc23, tc3, mclk);
+        timer.start(SAMPLE_RATE.hz());
+
+        Self {
+            buffer,
+            dac,
+            dmac,
+            _timer: timer,
+            _pin: pin.into(),
+        }
+    }
+
+    #[inline]
+    pub fn volume(&self) -> u8 {
+        self.buffer.volume()
+    }
+
+    /// 0 to `MAX_VOLUME`.
+    #[inline]
+    pub fn set_volume(&mut self, volume: u8) {
+        self.buffer.set_volume(volume)
+    }
+
+    #[inline]
+    pub fn underruns(&self) -> u32 {
+        self.buffer.underruns()
+    }
+
+    /// Which half the DMAC is in the middle of. The write-back descriptor always points at the
+    /// one after.
+    fn playing(&self) -> usize {
+        let next = unsafe { ptr::read_volatile(ptr::addr_of!(WRITE_BACK.descaddr)) };
+        if next == ptr::addr_of!(SECOND) as u32 {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Samples queued up behind the halves, see `DoubleBuffer::queued`.
+    #[inline]
+    pub fn queued(&self) -> usize {
+        self.buffer.queued()
+    }
+
+    /// Refills the half the DMAC just left. `write` does this too, but anything that waits on
+    /// the queue to drain has to call it itself.
+    pub fn service(&mut self) {
+        let playing = self.playing();
+        self.buffer.service(playing);
+
+        // A transfer error stops the channel, so pick it back up rather than go quiet for good
+        let channel = &self.dmac.channel[CHANNEL];
+        if channel.chintflag.read().terr().bit_is_set() {
+            channel.chintflag.write(|w| w.terr().set_bit());
+            channel.chctrla.modify(|_, w| w.enable().set_bit());
+        }
+    }
+
+    /// Stops the DMAC and leaves the DAC at the middle of its range.
+    pub fn stop(&mut self) {
+        let channel = &self.dmac.channel[CHANNEL];
+        channel.chctrla.modify(|_, w| w.enable().clear_bit());
+        while channel.chctrla.read().enable().bit_is_set() {}
+        self.dac.data[0].write(|w| unsafe { w.data().bits(0x8000) });
+    }
+}
+
+impl AudioSink for Dac {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        self.service();
+        self.buffer.write(samples)
+    }
+}
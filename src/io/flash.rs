@@ -0,0 +1,185 @@
+use bsp::hal::nvm::{retrieve_flash_size, EraseGranularity, Nvm, BLOCKSIZE, PAGESIZE};
+use itsybitsy_m4 as bsp;
+
+use crate::printer::slots::{self, SEQUENCE_OFFSET};
+use crate::printer::PrintSink;
+use crate::settings::{SettingsStore, SETTINGS_SIZE};
+
+/// Flash blocks per printout. A full screen with both margins maxed out is just under 32 KiB.
+const SLOT_BLOCKS: u32 = 4;
+const SLOT_SIZE: u32 = SLOT_BLOCKS * BLOCKSIZE;
+/// Printouts kept before the oldest gets overwritten
+const SLOTS: u32 = 4;
+
+const PAGE_WORDS: usize = PAGESIZE as usize / 4;
+const SETTINGS_WORDS: usize = SETTINGS_SIZE.div_ceil(4);
+
+/// Start of the printouts, with the settings in the block just below them. memory.x keeps the
+/// firmware out of both.
+fn prints_base() -> u32 {
+    retrieve_flash_size() - SLOTS * SLOT_SIZE
+}
+
+fn settings_base() -> u32 {
+    prints_base() - BLOCKSIZE
+}
+
+/// Keeps `Settings` in a block of flash of its own. Every save erases the whole block, which is
+/// fine for something that only changes when someone goes through the menu.
+pub struct FlashSettings {
+    nvm: Nvm,
+}
+
+impl FlashSettings {
+    pub fn new(nvm: Nvm) -> Self {
+        Self { nvm }
+    }
+
+    /// Hands the flash controller back, e.g. to `FlashPrints`.
+    pub fn free(self) -> Nvm {
+        self.nvm
+    }
+}
+
+impl SettingsStore for FlashSettings {
+    fn read(&mut self, buf: &mut [u8]) -> bool {
+        let stored = unsafe { &*(settings_base() as *const [u8; SETTINGS_SIZE]) };
+        let len = buf.len().min(SETTINGS_SIZE);
+        buf[..len].copy_from_slice(&stored[..len]);
+        true
+    }
+
+    fn write(&mut self, buf: &[u8]) -> bool {
+        if buf.len() > SETTINGS_SIZE {
+            return false;
+        }
+        let mut words = [0xFFFF_FFFF; SETTINGS_WORDS];
+        for (i, byte) in buf.iter().enumerate() {
+            let shift = (i % 4) * 8;
+            words[i / 4] = (words[i / 4] & !(0xFF << shift)) | ((*byte as u32) << shift);
+        }
+        let address = settings_base();
+        unsafe {
+            self.nvm
+                .erase(address, 1, EraseGranularity::Block)
+                .and_then(|_| self.nvm.write_from_slice(address, &words))
+                .is_ok()
+        }
+    }
+}
+
+/// Keeps the last few printouts as BMPs at the very end of flash, where they can be dumped
+/// with a debugger or a bootloader.
+pub struct FlashPrints {
+    nvm: Nvm,
+    base: u32,
+    /// Slot the next printout goes into
+    next: u32,
+    /// Stamped into the next printout's header so `new` can tell which one is newest
+    sequence: u32,
+    /// Where the page being filled goes, `None` when there's no printout underway
+    address: Option<u32>,
+    end: u32,
+    page: [u32; PAGE_WORDS],
+    filled: usize,
+    /// Bytes of the printout underway written so far
+    written: usize,
+}
+
+impl FlashPrints {
+    pub fn new(nvm: Nvm) -> Self {
+        let base = prints_base();
+        // Carry on after the newest printout, whichever slot that's in
+        let mut sequences = [None; SLOTS as usize];
+        for (slot, sequence) in sequences.iter_mut().enumerate() {
+            let header = unsafe { &*((base + slot as u32 * SLOT_SIZE) as *const [u8; 10]) };
+            *sequence = slots::sequence(header);
+        }
+        let (next, sequence) = slots::next_slot(&sequences);
+
+        Self {
+            nvm,
+            base,
+            next: next as u32,
+            sequence,
+            address: None,
+            end: 0,
+            page: [0xFFFF_FFFF; PAGE_WORDS],
+            filled: 0,
+            written: 0,
+        }
+    }
+
+    /// Printout `slot` as stored, if there's one there.
+    pub fn printout(&self, slot: u32) -> Option<&'static [u8]> {
+        let start = (self.base + (slot % SLOTS) * SLOT_SIZE) as *const u8;
+        unsafe {
+            let header = core::slice::from_raw_parts(start, 6);
+            if header[..2] != *b"BM" {
+                return None;
+            }
+            let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+            Some(core::slice::from_raw_parts(
+                start,
+                len.min(SLOT_SIZE) as usize,
+            ))
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(address) = self.address {
+            if self.filled > 0 && address < self.end {
+                let _ = unsafe { self.nvm.write_from_slice(address, &self.page) };
+                self.address = Some(address + PAGESIZE);
+            }
+        }
+        self.page = [0xFFFF_FFFF; PAGE_WORDS];
+        self.filled = 0;
+    }
+}
+
+impl PrintSink for FlashPrints {
+    fn begin(&mut self, len: usize) -> bool {
+        if len as u32 > SLOT_SIZE {
+            return false;
+        }
+        let address = self.base + self.next * SLOT_SIZE;
+        if unsafe {
+            self.nvm
+                .erase(address, SLOT_BLOCKS, EraseGranularity::Block)
+        }
+        .is_err()
+        {
+            return false;
+        }
+        self.next = (self.next + 1) % SLOTS;
+        self.address = Some(address);
+        self.end = address + SLOT_SIZE;
+        self.filled = 0;
+        self.written = 0;
+        true
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            let byte = match self.written.checked_sub(SEQUENCE_OFFSET) {
+                Some(i) if i < 4 => self.sequence.to_le_bytes()[i],
+                _ => *byte,
+            };
+            self.written += 1;
+            let word = &mut self.page[self.filled / 4];
+            let shift = (self.filled % 4) * 8;
+            *word = (*word & !(0xFF << shift)) | ((byte as u32) << shift);
+            self.filled += 1;
+            if self.filled == PAGESIZE as usize {
+                self.flush();
+            }
+        }
+    }
+
+    fn end(&mut self) {
+        self.flush();
+        self.address = None;
+        self.sequence = self.sequence.wrapping_add(1);
+    }
+}
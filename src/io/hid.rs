@@ -1,21 +1,7 @@
 use itsybitsy_m4::hal::prelude::*;
 use itsybitsy_m4::hal::gpio::v2::*;
 
-use itsybitsy_m4;
-use itsybitsy_m4::Pins;
-use itsybitsy_m4::hal::pac::Peripherals;
-use itsybitsy_m4::pac::PORT;
-
-pub enum Pressed {
-    Up,
-    Down,
-    Left,
-    Right,
-    None,
-    A,
-    B,
-    Menu,
-}
+use super::input::*;
 
 pub struct Buttons {
     // pub(crate) a: Pin<PA22, atsamd_hal::gpio::v2::Input<atsamd_hal::gpio::v2::PullUp>>,
@@ -28,24 +14,31 @@ pub struct Buttons {
     pub menu: Pin<PA12, Input<PullUp>>,
 }
 
-impl Buttons {
-    pub fn pressed(&self) -> Pressed {
+impl InputSource for Buttons {
+    /// The buttons pull their pins low when pressed.
+    fn held(&mut self) -> u8 {
+        let mut held = 0;
+        if self.right.is_low().unwrap_or(false) {
+            held |= BTN_RIGHT;
+        }
+        if self.left.is_low().unwrap_or(false) {
+            held |= BTN_LEFT;
+        }
         if self.up.is_low().unwrap_or(false) {
-            Pressed::Up
-        } else if self.down.is_low().unwrap_or(false) {
-            Pressed::Down
-        } else if self.right.is_low().unwrap_or(false) {
-            Pressed::Right
-        } else if self.left.is_low().unwrap_or(false) {
-            Pressed::Left
-        } else if self.a.is_low().unwrap_or(false) {
-            Pressed::A
-        } else if self.b.is_low().unwrap_or(false) {
-            Pressed::B
-        } else if self.menu.is_low().unwrap_or(false) {
-            Pressed::Menu
-        } else {
-            Pressed::None
+            held |= BTN_UP;
+        }
+        if self.down.is_low().unwrap_or(false) {
+            held |= BTN_DOWN;
+        }
+        if self.a.is_low().unwrap_or(false) {
+            held |= BTN_A;
+        }
+        if self.b.is_low().unwrap_or(false) {
+            held |= BTN_B;
+        }
+        if self.menu.is_low().unwrap_or(false) {
+            held |= BTN_MENU;
         }
+        held
     }
 }
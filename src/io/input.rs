@@ -0,0 +1,16 @@
+/// One bit per physical button on the board, as returned by `InputSource::held`. The ones the
+/// Game Boy also has sit where the joypad expects them, see io/joypad.rs.
+pub const BTN_RIGHT: u8 = 0b0000_0001;
+pub const BTN_LEFT: u8 = 0b0000_0010;
+pub const BTN_UP: u8 = 0b0000_0100;
+pub const BTN_DOWN: u8 = 0b0000_1000;
+pub const BTN_A: u8 = 0b0001_0000;
+pub const BTN_B: u8 = 0b0010_0000;
+pub const BTN_MENU: u8 = 0b0100_0000;
+
+/// Anything that can tell us which buttons are held down right now. The board reads its pins,
+/// tests and host builds can feed in whatever they like.
+pub trait InputSource {
+    /// Returns a mask of the `BTN_*` bits currently held.
+    fn held(&mut self) -> u8;
+}
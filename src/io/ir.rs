@@ -0,0 +1,25 @@
+use itsybitsy_m4::hal::gpio::v2::*;
+use itsybitsy_m4::hal::prelude::*;
+
+use crate::infrared::IrTransport;
+
+/// An IR LED on A4 and a phototransistor pulling A5 low when lit. Both ends are in real time,
+/// so the timestamps don't matter here.
+pub struct IrPins {
+    pub led: Pin<PA04, PushPullOutput>,
+    pub sensor: Pin<PA06, Input<PullUp>>,
+}
+
+impl IrTransport for IrPins {
+    fn led(&mut self, on: bool, _at: u64) {
+        let _ = if on {
+            self.led.set_high()
+        } else {
+            self.led.set_low()
+        };
+    }
+
+    fn sensing(&mut self, _at: u64) -> bool {
+        self.sensor.is_low().unwrap_or(false)
+    }
+}
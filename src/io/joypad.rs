@@ -0,0 +1,138 @@
+use super::input::*;
+
+/// Game Boy buttons, laid out so the low nibble is the direction keys and the high nibble the
+/// action buttons, the two halves P14 and P15 select between.
+pub const RIGHT: u8 = 0b0000_0001;
+pub const LEFT: u8 = 0b0000_0010;
+pub const UP: u8 = 0b0000_0100;
+pub const DOWN: u8 = 0b0000_1000;
+pub const A: u8 = 0b0001_0000;
+pub const B: u8 = 0b0010_0000;
+pub const SELECT: u8 = 0b0100_0000;
+pub const START: u8 = 0b1000_0000;
+
+/// Interrupt request bit as it sits in IF
+pub const JOYPAD_INTERRUPT: u8 = 0b0001_0000;
+
+const SELECT_DIRECTIONS: u8 = 0b0001_0000;
+const SELECT_ACTIONS: u8 = 0b0010_0000;
+
+/// Maps the board's buttons straight onto the Game Boy's. The menu button has no counterpart.
+#[inline]
+pub fn from_buttons(held: u8) -> u8 {
+    held & (BTN_RIGHT | BTN_LEFT | BTN_UP | BTN_DOWN | BTN_A | BTN_B)
+}
+
+/// P1/JOYP at 0xFF00. The game pulls P14 and/or P15 low to pick a row of buttons, and reads the
+/// row back on P10-P13 where a pressed button reads 0.
+pub struct Joypad {
+    /// P14/P15 as last written
+    select: u8,
+    /// Game Boy buttons held, see the constants above
+    held: u8,
+    /// P10-P13 as of the last change, the interrupt fires when one of them falls
+    lines: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            held: 0,
+            lines: 0x0F,
+        }
+    }
+
+    #[inline]
+    fn output(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.held & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            pressed |= self.held >> 4;
+        }
+        !pressed & 0x0F
+    }
+
+    #[inline]
+    fn refresh(&mut self) -> u8 {
+        let lines = self.output();
+        let fell = self.lines & !lines;
+        self.lines = lines;
+        if fell != 0 {
+            JOYPAD_INTERRUPT
+        } else {
+            0
+        }
+    }
+
+    /// Whether any of the selected buttons are pressed, pulling one of P10-P13 low.
+    #[inline]
+    pub fn pressed(&self) -> bool {
+        self.output() != 0x0F
+    }
+
+    /// Updates the held Game Boy buttons and returns the IF bits that raises.
+    #[inline]
+    pub fn set_held(&mut self, held: u8) -> u8 {
+        self.held = held;
+        self.refresh()
+    }
+
+    /// Reads the board through `input` and returns the IF bits that raises.
+    #[inline]
+    pub fn poll<I: InputSource>(&mut self, input: &mut I) -> u8 {
+        self.set_held(from_buttons(input.held()))
+    }
+
+    #[inline]
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.output()
+    }
+
+    /// Only P14 and P15 are writable. Selecting a row with a button already down counts as a
+    /// falling edge too, so this can raise the interrupt as well.
+    #[inline]
+    pub fn write(&mut self, val: u8) -> u8 {
+        self.select = val & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+        self.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake(u8);
+
+    impl InputSource for Fake {
+        fn held(&mut self) -> u8 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn rows_are_selected_by_p14_and_p15() {
+        let mut joypad = Joypad::new();
+        joypad.poll(&mut Fake(BTN_UP | BTN_LEFT | BTN_A));
+
+        assert_eq!(joypad.read(), 0xFF);
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xE9);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xDE);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC8);
+    }
+
+    #[test]
+    fn falling_line_raises_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        assert_eq!(joypad.poll(&mut Fake(BTN_UP)), 0);
+        assert_eq!(joypad.poll(&mut Fake(BTN_A)), JOYPAD_INTERRUPT);
+        assert_eq!(joypad.poll(&mut Fake(BTN_A | BTN_B)), JOYPAD_INTERRUPT);
+        assert_eq!(joypad.poll(&mut Fake(BTN_B)), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use super::chords::ChordSettings;
+use super::input::*;
+use super::joypad::{SELECT, START};
+
+/// The emulator menu, which a long press of menu opens and another one closes. The game is
+/// paused while it's open and the buttons change settings instead:
+///
+/// - A and B turn turbo on or off for that button
+/// - left and right swap what tapping menu and menu+B stand for, Start and Select
+pub struct Menu {
+    open: bool,
+    /// Buttons held as of the last update, so holding one down only counts once
+    last: u8,
+    /// Settings got changed since the menu was opened
+    changed: bool,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            last: 0,
+            changed: false,
+        }
+    }
+
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Opens the menu, or closes it if it's already open. Returns true if it just closed with
+    /// settings changed, i.e. they want saving.
+    pub fn toggle(&mut self) -> bool {
+        self.open = !self.open;
+        // Menu is still down from the long press, and whatever else is held was meant for the game
+        self.last = 0xFF;
+        let save = !self.open && self.changed;
+        self.changed = false;
+        save
+    }
+
+    /// Feeds in the debounced `BTN_*` bits, see `Chords::held`, and applies whatever got pressed
+    /// to `settings`. Returns true if anything changed.
+    pub fn update(&mut self, held: u8, settings: &mut ChordSettings) -> bool {
+        let pressed = held & !self.last;
+        self.last = held;
+        if !self.open || held & BTN_MENU != 0 {
+            return false;
+        }
+
+        let before = *settings;
+        if pressed & BTN_A != 0 {
+            settings.turbo_a = !settings.turbo_a;
+        }
+        if pressed & BTN_B != 0 {
+            settings.turbo_b = !settings.turbo_b;
+        }
+        if pressed & (BTN_LEFT | BTN_RIGHT) != 0 {
+            (settings.menu_tap, settings.menu_b) = if settings.menu_tap == START {
+                (SELECT, START)
+            } else {
+                (START, SELECT)
+            };
+        }
+
+        let changed = *settings != before;
+        self.changed |= changed;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_turbo_once_per_press() {
+        let mut menu = Menu::new();
+        let mut settings = ChordSettings::default();
+        assert!(!menu.update(BTN_A, &mut settings));
+
+        menu.toggle();
+        // A was already down when the menu opened
+        assert!(!menu.update(BTN_A, &mut settings));
+        menu.update(0, &mut settings);
+        assert!(menu.update(BTN_A, &mut settings));
+        assert!(!menu.update(BTN_A, &mut settings));
+        assert!(settings.turbo_a);
+        assert!(menu.update(BTN_B, &mut settings));
+        assert!(settings.turbo_b);
+
+        assert!(menu.toggle());
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn swaps_start_and_select() {
+        let mut menu = Menu::new();
+        let mut settings = ChordSettings::default();
+        menu.toggle();
+        menu.update(0, &mut settings);
+        menu.update(BTN_RIGHT, &mut settings);
+        assert_eq!((settings.menu_tap, settings.menu_b), (SELECT, START));
+        menu.update(0, &mut settings);
+        menu.update(BTN_LEFT, &mut settings);
+        assert_eq!((settings.menu_tap, settings.menu_b), (START, SELECT));
+        // Swapped back, but there's no harm in saving
+        assert!(menu.toggle());
+    }
+
+    #[test]
+    fn nothing_to_save() {
+        let mut menu = Menu::new();
+        assert!(!menu.toggle());
+        assert!(!menu.toggle());
+    }
+}
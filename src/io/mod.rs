@@ -1,2 +1,17 @@
+pub mod chords;
+#[cfg(feature = "board")]
+pub mod dac;
+#[cfg(feature = "board")]
+pub mod flash;
+#[cfg(feature = "board")]
 pub mod hid;
+pub mod input;
+#[cfg(feature = "board")]
+pub mod ir;
+pub mod joypad;
+pub mod menu;
 pub mod spi;
+#[cfg(feature = "board")]
+pub mod uart;
+#[cfg(feature = "board")]
+pub mod usb;
@@ -0,0 +1,43 @@
+use bsp::hal::clock::GenericClockController;
+use bsp::hal::pac::{MCLK, SERCOM3};
+use bsp::hal::prelude::*;
+use bsp::{Uart, UartRx, UartTx};
+use itsybitsy_m4 as bsp;
+
+use crate::serial::{ByteStream, FramedLink};
+
+/// Fast enough that a frame takes less time than a CGB fast-clock transfer.
+pub const LINK_BAUD: u32 = 1_000_000;
+
+/// Reads the master spins through waiting for the other board's READY before it gives up.
+pub const LINK_TIMEOUT: u32 = 20_000;
+
+impl ByteStream for Uart {
+    fn write_byte(&mut self, byte: u8) {
+        let _ = nb::block!(self.write(byte));
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        match self.read() {
+            Ok(byte) => Some(byte),
+            Err(nb::Error::WouldBlock) => None,
+            Err(nb::Error::Other(_)) => {
+                // Overrun or framing error, the decoder will find its feet again
+                self.flush_rx_buffer();
+                None
+            }
+        }
+    }
+}
+
+/// Link cable to another board over the RX and TX pins, which get crossed over between the two.
+pub fn link(
+    clocks: &mut GenericClockController,
+    sercom3: SERCOM3,
+    mclk: &mut MCLK,
+    rx: impl Into<UartRx>,
+    tx: impl Into<UartTx>,
+) -> FramedLink<Uart> {
+    let uart = bsp::uart(clocks, LINK_BAUD.hz(), sercom3, mclk, rx, tx);
+    FramedLink::new(uart, LINK_TIMEOUT)
+}
@@ -0,0 +1,70 @@
+use bsp::hal::usb::usb_device::bus::UsbBusAllocator;
+use bsp::hal::usb::usb_device::prelude::*;
+use bsp::hal::usb::UsbBus;
+use itsybitsy_m4 as bsp;
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+use crate::serial::{ByteStream, FramedLink};
+
+/// Reads the master spins through waiting for the other end's READY. USB round trips are a
+/// frame or two, so this is a good deal longer than the UART's.
+pub const LINK_TIMEOUT: u32 = 200_000;
+
+/// Writes retried while the host isn't draining the endpoint before the byte is dropped.
+const WRITE_RETRIES: u32 = 10_000;
+
+/// The board's USB port as a CDC-ACM serial device. Shows up as /dev/ttyACM* on the PC, where
+/// a host-side gbc-m4 or a TCP-to-serial shim in front of another emulator can open it.
+///
+/// Nothing here runs off the USB interrupts, the device is polled on every byte instead.
+pub struct UsbStream {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+}
+
+impl UsbStream {
+    pub fn new(allocator: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let serial = SerialPort::new(allocator);
+        let device = UsbDeviceBuilder::new(allocator, UsbVidPid(0x16c0, 0x27dd))
+            .manufacturer("gbc-m4")
+            .product("Link cable")
+            .serial_number("GBC")
+            .device_class(USB_CLASS_CDC)
+            .build();
+        Self { device, serial }
+    }
+
+    #[inline]
+    fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.serial]);
+    }
+}
+
+impl ByteStream for UsbStream {
+    fn write_byte(&mut self, byte: u8) {
+        for _ in 0..WRITE_RETRIES {
+            self.poll();
+            // Nobody has the port open, so there's no one to talk to
+            if self.device.state() != UsbDeviceState::Configured || !self.serial.dtr() {
+                return;
+            }
+            if let Ok(1) = self.serial.write(&[byte]) {
+                return;
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.poll();
+        let mut buf = [0u8; 1];
+        match self.serial.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+}
+
+/// Link cable to a PC over the USB port, framed the same as the board-to-board UART link.
+pub fn link(allocator: &'static UsbBusAllocator<UsbBus>) -> FramedLink<UsbStream> {
+    FramedLink::new(UsbStream::new(allocator), LINK_TIMEOUT)
+}
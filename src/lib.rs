@@ -0,0 +1,20 @@
+// The emulator itself. main.rs is just the board around it, everything in here builds and
+// tests on the host too.
+#![no_std]
+#![recursion_limit = "1024"]
+// Everything gets built through new(), a lot of them as const fns for statics
+#![allow(clippy::new_without_default)]
+
+pub mod apu;
+pub mod cpu;
+pub mod dma;
+mod harness;
+pub mod infrared;
+pub mod io;
+pub mod ppu;
+pub mod printer;
+pub mod serial;
+pub mod settings;
+pub mod timer;
+// mod cart;
+// mod mmu;
@@ -7,25 +7,36 @@
 
 use panic_halt as _;
 
-// mod cpu;
-// mod cart;
-mod io;
-// mod mmu;
-
-
 use itsybitsy_m4 as bsp;
 use bsp::hal;
 
 use bsp::entry;
 use hal::clock::GenericClockController;
 use hal::delay::Delay;
+use hal::nvm::Nvm;
 use hal::pac::{CorePeripherals, Peripherals};
 use hal::prelude::*;
 use hal::watchdog::{Watchdog, WatchdogTimeout};
 // use hal::gpio::v2::Pins;
 
-use io::hid;
-use crate::io::hid::{Buttons, Pressed};
+use gbc_m4::apu::Layout;
+use gbc_m4::cpu::CPU;
+use gbc_m4::io::chords::Chords;
+use gbc_m4::io::dac::{self, AudioBuffer, Dac};
+use gbc_m4::io::flash::FlashSettings;
+use gbc_m4::io::hid::Buttons;
+use gbc_m4::io::input::InputSource;
+use gbc_m4::io::menu::Menu;
+use gbc_m4::settings::Settings;
+
+/// The cartridge, see build.rs
+static ROM: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/rom.gb"));
+
+/// Game Boy T-cycles run between button polls, about the 7ms io/chords.rs counts in.
+const SLICE_CYCLES: u32 = 29_360;
+/// Samples the DAC gets to have queued before the emulator waits for it. Audio is the only
+/// clock we've got, so this is what keeps the game running at the right speed.
+const AUDIO_AHEAD: usize = 1024;
 
 #[entry]
 fn main() -> ! {
@@ -41,7 +52,10 @@ fn main() -> ! {
     let mut delay = Delay::new(core.SYST, &mut clocks);
     delay.delay_ms(400u16);
 
-    let mut pins = bsp::Pins::new(peripherals.PORT);
+    let mut store = FlashSettings::new(Nvm::new(peripherals.NVMCTRL));
+    let mut settings = Settings::load(&mut store);
+
+    let pins = bsp::Pins::new(peripherals.PORT);
     // let mut red_led = pins.d13.into_push_pull_output();
     let mut wdt = Watchdog::new(peripherals.WDT);
     wdt.start(WatchdogTimeout::Cycles256 as u8);
@@ -49,7 +63,7 @@ fn main() -> ! {
     // Turns off the indicator until we need it
     let mut _indicator = pins.d13.into_push_pull_output();
 
-    let btns = Buttons {
+    let mut btns = Buttons {
         a: pins.scl.into_pull_up_input(),
         b: pins.d7.into_pull_up_input(),
         up: pins.d10.into_pull_up_input(),
@@ -59,11 +73,55 @@ fn main() -> ! {
         menu: pins.sda.into_pull_up_input(),
     };
 
+    let mut chords = Chords::new(settings.chords);
+    let mut menu = Menu::new();
+
+    // Both are far too big for the stack to hold on to for good
+    let cpu = cortex_m::singleton!(: CPU = CPU::new(true)).unwrap();
+    cpu.load_rom(ROM);
+    cpu.set_audio_format(dac::SAMPLE_RATE, Layout::Mono);
+    let buffer = cortex_m::singleton!(: AudioBuffer = AudioBuffer::new()).unwrap();
+    let mut dac = Dac::new(
+        &mut clocks,
+        &mut peripherals.MCLK,
+        peripherals.DAC,
+        peripherals.DMAC,
+        peripherals.TC3,
+        pins.a0,
+        buffer,
+    );
+    // Lights up for good once the CPU has hung itself
+    let mut locked = false;
+
     loop {
-        delay.delay_ms(7u8);
-        _ = match btns.pressed() {
-            Pressed::None => _indicator.set_low(),
-            _ => _indicator.set_high()
+        while dac.queued() >= AUDIO_AHEAD {
+            dac.service();
+            wdt.feed();
+        }
+
+        let held = chords.update(btns.held());
+        if chords.menu_requested() && menu.toggle() {
+            settings.save(&mut store);
+        }
+        if menu.is_open() {
+            if menu.update(chords.held(), &mut settings.chords) {
+                chords.set_settings(settings.chords);
+            }
+            // The game's paused, so nothing's paced by the audio any more
+            _ = _indicator.set_high();
+            delay.delay_ms(7u8);
+            wdt.feed();
+            continue;
+        }
+
+        cpu.set_buttons(held);
+        locked |= cpu.run(SLICE_CYCLES).is_err();
+        cpu.drain_audio(&mut dac);
+
+        _ = if held == 0 && !locked {
+            _indicator.set_low()
+        } else {
+            _indicator.set_high()
         };
         wdt.feed();
     }
@@ -0,0 +1,58 @@
+/// A byte out of the CGB background attribute map, which lives in VRAM bank 1 at the same offset
+/// as the tile number it describes in bank 0.
+///
+/// ```text
+/// Bit 7    BG-to-OAM priority (0 = use OAM priority bit, 1 = BG over OBJ)
+/// Bit 6    Vertical flip
+/// Bit 5    Horizontal flip
+/// Bit 4    Not used
+/// Bit 3    Tile VRAM bank
+/// Bit 2-0  Background palette number
+/// ```
+#[derive(Clone, Copy, Default)]
+pub struct BgAttributes(pub u8);
+
+impl BgAttributes {
+    #[inline]
+    pub fn palette(&self) -> u8 {
+        self.0 & 0x07
+    }
+
+    #[inline]
+    pub fn bank(&self) -> usize {
+        ((self.0 >> 3) & 0x01) as usize
+    }
+
+    #[inline]
+    pub fn x_flip(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    #[inline]
+    pub fn y_flip(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    #[inline]
+    pub fn priority(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_field() {
+        let attr = BgAttributes(0b1110_1101);
+        assert_eq!(attr.palette(), 5);
+        assert_eq!(attr.bank(), 1);
+        assert!(attr.x_flip() && attr.y_flip() && attr.priority());
+
+        // Bit 4 doesn't mean anything
+        let attr = BgAttributes(0x10);
+        assert_eq!((attr.palette(), attr.bank()), (0, 0));
+        assert!(!attr.x_flip() && !attr.y_flip() && !attr.priority());
+    }
+}
@@ -0,0 +1,593 @@
+// Pixel processing unit, one scanline at a time
+
+mod attributes;
+mod palette;
+
+pub use attributes::*;
+pub use palette::*;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xA0;
+
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const LINES_PER_FRAME: u8 = 154;
+const SPRITES_PER_LINE: usize = 10;
+
+// LCDC bits
+const LCDC_BG_ENABLE: u8 = 0b0000_0001;
+const LCDC_OBJ_ENABLE: u8 = 0b0000_0010;
+const LCDC_OBJ_SIZE: u8 = 0b0000_0100;
+const LCDC_BG_MAP: u8 = 0b0000_1000;
+const LCDC_TILE_DATA: u8 = 0b0001_0000;
+const LCDC_WINDOW_ENABLE: u8 = 0b0010_0000;
+const LCDC_WINDOW_MAP: u8 = 0b0100_0000;
+const LCDC_ENABLE: u8 = 0b1000_0000;
+
+// STAT interrupt sources
+const STAT_HBLANK: u8 = 0b0000_1000;
+const STAT_VBLANK: u8 = 0b0001_0000;
+const STAT_OAM: u8 = 0b0010_0000;
+const STAT_LYC: u8 = 0b0100_0000;
+
+/// Interrupt request bits as they sit in IF
+pub const VBLANK_INTERRUPT: u8 = 0b0000_0001;
+pub const STAT_INTERRUPT: u8 = 0b0000_0010;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+pub struct Ppu {
+    cgb: bool,
+    /// Bank 0 holds tile data and maps, CGB adds bank 1 for more tiles and the BG attribute map
+    vram: [[u8; VRAM_BANK_SIZE]; 2],
+    /// VBK, the bank the CPU sees at 0x8000-0x9FFF
+    vbk: usize,
+    oam: [u8; OAM_SIZE],
+    lcdc: u8,
+    /// Only the interrupt source selects (bits 3-6) are stored, the rest is derived on read
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    /// Internal line counter of the window, only advances on lines the window was drawn on
+    window_line: u8,
+    mode: Mode,
+    /// Dots spent on the current line
+    dots: u32,
+    /// The STAT interrupt fires on the rising edge of the OR of all enabled sources
+    stat_line: bool,
+    bg_palette: CgbPalette,
+    obj_palette: CgbPalette,
+    frame_ready: bool,
+    /// Set when a visible line enters HBlank, HDMA copies a block each time
+    hblank_started: bool,
+    /// BGR555 pixels, see palette.rs
+    pub frame: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl Ppu {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            vram: [[0u8; VRAM_BANK_SIZE]; 2],
+            vbk: 0,
+            oam: [0u8; OAM_SIZE],
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            window_line: 0,
+            mode: Mode::OamScan,
+            dots: 0,
+            stat_line: false,
+            bg_palette: CgbPalette::new(),
+            obj_palette: CgbPalette::new(),
+            frame_ready: false,
+            hblank_started: false,
+            frame: [DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns true once per frame, when the PPU enters VBlank.
+    #[inline]
+    pub fn frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    /// Returns true once per visible line, when the PPU enters HBlank.
+    #[inline]
+    pub fn hblank_started(&mut self) -> bool {
+        let started = self.hblank_started;
+        self.hblank_started = false;
+        started
+    }
+
+    /// Advances the PPU by `dots` clocks and returns the IF bits it wants raised.
+    pub fn tick(&mut self, dots: u32) -> u8 {
+        if self.lcdc & LCDC_ENABLE == 0 {
+            return 0;
+        }
+
+        let mut ints = 0;
+        self.dots += dots;
+
+        loop {
+            let end = match self.mode {
+                Mode::OamScan => OAM_SCAN_DOTS,
+                Mode::Drawing => OAM_SCAN_DOTS + DRAWING_DOTS,
+                Mode::HBlank | Mode::VBlank => DOTS_PER_LINE,
+            };
+            if self.dots < end {
+                break;
+            }
+
+            match self.mode {
+                Mode::OamScan => self.mode = Mode::Drawing,
+                Mode::Drawing => {
+                    self.render_line();
+                    self.hblank_started = true;
+                    self.mode = Mode::HBlank;
+                }
+                Mode::HBlank | Mode::VBlank => {
+                    self.dots -= DOTS_PER_LINE;
+                    self.ly = (self.ly + 1) % LINES_PER_FRAME;
+                    if self.ly == 0 {
+                        self.window_line = 0;
+                        self.mode = Mode::OamScan;
+                    } else if self.ly as usize == SCREEN_HEIGHT {
+                        self.frame_ready = true;
+                        self.mode = Mode::VBlank;
+                        ints |= VBLANK_INTERRUPT;
+                    } else if self.mode == Mode::HBlank {
+                        self.mode = Mode::OamScan;
+                    }
+                }
+            }
+            ints |= self.update_stat_line();
+        }
+
+        ints | self.update_stat_line()
+    }
+
+    #[inline]
+    fn update_stat_line(&mut self) -> u8 {
+        let line = match self.mode {
+            Mode::HBlank => self.stat & STAT_HBLANK != 0,
+            Mode::VBlank => self.stat & STAT_VBLANK != 0,
+            Mode::OamScan => self.stat & STAT_OAM != 0,
+            Mode::Drawing => false,
+        } || (self.stat & STAT_LYC != 0 && self.ly == self.lyc);
+
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising {
+            STAT_INTERRUPT
+        } else {
+            0
+        }
+    }
+
+    /// CGB palette memory belongs to the PPU while it's pushing pixels.
+    #[inline]
+    fn palette_accessible(&self) -> bool {
+        self.mode != Mode::Drawing
+    }
+
+    #[inline]
+    fn vram_accessible(&self) -> bool {
+        self.mode != Mode::Drawing
+    }
+
+    #[inline]
+    fn oam_accessible(&self) -> bool {
+        !matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    #[inline]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9fff if self.vram_accessible() => {
+                self.vram[self.vbk][(addr - 0x8000) as usize]
+            }
+            0xfe00..=0xfe9f if self.oam_accessible() => self.oam[(addr - 0xFE00) as usize],
+            0xff40 => self.lcdc,
+            0xff41 => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0x00 };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            0xff42 => self.scy,
+            0xff43 => self.scx,
+            0xff44 => self.ly,
+            0xff45 => self.lyc,
+            0xff47 => self.bgp,
+            0xff48 => self.obp0,
+            0xff49 => self.obp1,
+            0xff4a => self.wy,
+            0xff4b => self.wx,
+            0xff4f if self.cgb => 0xFE | self.vbk as u8,
+            0xff68 if self.cgb => self.bg_palette.read_spec(),
+            0xff69 if self.cgb => self.bg_palette.read_data(self.palette_accessible()),
+            0xff6a if self.cgb => self.obj_palette.read_spec(),
+            0xff6b if self.cgb => self.obj_palette.read_data(self.palette_accessible()),
+            _ => 0xFF,
+        }
+    }
+
+    #[inline]
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x8000..=0x9fff if self.vram_accessible() => {
+                self.vram[self.vbk][(addr - 0x8000) as usize] = val
+            }
+            0xfe00..=0xfe9f if self.oam_accessible() => self.oam[(addr - 0xFE00) as usize] = val,
+            0xff40 => self.write_lcdc(val),
+            0xff41 => self.stat = val & 0x78,
+            0xff42 => self.scy = val,
+            0xff43 => self.scx = val,
+            0xff44 => {} // LY is read only
+            0xff45 => self.lyc = val,
+            0xff47 => self.bgp = val,
+            0xff48 => self.obp0 = val,
+            0xff49 => self.obp1 = val,
+            0xff4a => self.wy = val,
+            0xff4b => self.wx = val,
+            0xff4f if self.cgb => self.vbk = (val & 0x01) as usize,
+            0xff68 if self.cgb => self.bg_palette.write_spec(val),
+            0xff69 if self.cgb => {
+                let accessible = self.palette_accessible();
+                self.bg_palette.write_data(val, accessible)
+            }
+            0xff6a if self.cgb => self.obj_palette.write_spec(val),
+            0xff6b if self.cgb => {
+                let accessible = self.palette_accessible();
+                self.obj_palette.write_data(val, accessible)
+            }
+            _ => {}
+        }
+    }
+
+    /// HDMA writes straight into the bank VBK selects, `offset` is relative to 0x8000.
+    #[inline]
+    pub fn write_vram(&mut self, offset: u16, val: u8) {
+        self.vram[self.vbk][(offset & 0x1FFF) as usize] = val;
+    }
+
+    /// OAM DMA writes straight into OAM, whatever mode the PPU is in.
+    #[inline]
+    pub fn write_oam(&mut self, index: usize, val: u8) {
+        self.oam[index] = val;
+    }
+
+    fn write_lcdc(&mut self, val: u8) {
+        let was_on = self.lcdc & LCDC_ENABLE != 0;
+        let on = val & LCDC_ENABLE != 0;
+        self.lcdc = val;
+
+        if was_on && !on {
+            self.ly = 0;
+            self.dots = 0;
+            self.window_line = 0;
+            self.mode = Mode::HBlank;
+        } else if !was_on && on {
+            self.dots = 0;
+            self.mode = Mode::OamScan;
+        }
+    }
+
+    /// Returns the two bitplanes of `row` of the tile `tile` points at in `bank`, honoring
+    /// LCDC's addressing mode.
+    #[inline]
+    fn bg_tile_row(&self, bank: usize, tile: u8, row: u8) -> (u8, u8) {
+        let base = if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        };
+        let addr = base + row as usize * 2;
+        (self.vram[bank][addr], self.vram[bank][addr + 1])
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly;
+        let mut line = [0u16; SCREEN_WIDTH];
+        // Color numbers of the background, sprites need them to resolve priority
+        let mut bg_color = [0u8; SCREEN_WIDTH];
+        // CGB tiles flagged as drawing over sprites
+        let mut bg_priority = [false; SCREEN_WIDTH];
+
+        // On DMG, LCDC bit 0 blanks the background and the window
+        let bg_enabled = self.cgb || self.lcdc & LCDC_BG_ENABLE != 0;
+        let window =
+            bg_enabled && self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.wy <= ly && self.wx <= 166;
+        let mut window_drawn = false;
+
+        for x in 0..SCREEN_WIDTH {
+            if !bg_enabled {
+                line[x] = DMG_SHADES[0];
+                continue;
+            }
+
+            let (map, px, py) = if window && x + 7 >= self.wx as usize {
+                window_drawn = true;
+                let map = if self.lcdc & LCDC_WINDOW_MAP != 0 {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                (map, (x + 7 - self.wx as usize) as u8, self.window_line)
+            } else {
+                let map = if self.lcdc & LCDC_BG_MAP != 0 {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                (
+                    map,
+                    self.scx.wrapping_add(x as u8),
+                    self.scy.wrapping_add(ly),
+                )
+            };
+
+            let offset = map + (py as usize / 8) * 32 + px as usize / 8;
+            let tile = self.vram[0][offset];
+            let attr = if self.cgb {
+                BgAttributes(self.vram[1][offset])
+            } else {
+                BgAttributes::default()
+            };
+
+            let row = if attr.y_flip() { 7 - py % 8 } else { py % 8 };
+            let (lo, hi) = self.bg_tile_row(attr.bank(), tile, row);
+            let bit = if attr.x_flip() { px % 8 } else { 7 - (px % 8) };
+            let color = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+
+            bg_color[x] = color;
+            bg_priority[x] = attr.priority();
+            line[x] = if self.cgb {
+                self.bg_palette.color(attr.palette(), color)
+            } else {
+                dmg_color(self.bgp, color)
+            };
+        }
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(&mut line, &bg_color, &bg_priority);
+        }
+
+        let start = ly as usize * SCREEN_WIDTH;
+        self.frame[start..start + SCREEN_WIDTH].copy_from_slice(&line);
+    }
+
+    fn render_sprites(
+        &self,
+        line: &mut [u16; SCREEN_WIDTH],
+        bg_color: &[u8; SCREEN_WIDTH],
+        bg_priority: &[bool; SCREEN_WIDTH],
+    ) {
+        let ly = self.ly as i16;
+        // On CGB, clearing LCDC bit 0 strips the background of any priority over sprites
+        let master_priority = !self.cgb || self.lcdc & LCDC_BG_ENABLE != 0;
+        let height: i16 = if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        };
+
+        // OAM scan, the first ten sprites on the line in OAM order
+        let mut sprites = [0usize; SPRITES_PER_LINE];
+        let mut count = 0;
+        for i in 0..OAM_SIZE / 4 {
+            let y = self.oam[i * 4] as i16 - 16;
+            if ly >= y && ly < y + height {
+                sprites[count] = i;
+                count += 1;
+                if count == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // DMG gives the lower X coordinate priority, CGB sticks to OAM order
+        if !self.cgb {
+            for i in 1..count {
+                let mut j = i;
+                while j > 0 && self.oam[sprites[j - 1] * 4 + 1] > self.oam[sprites[j] * 4 + 1] {
+                    sprites.swap(j - 1, j);
+                    j -= 1;
+                }
+            }
+        }
+
+        // A pixel belongs to the highest priority opaque sprite, even if the background hides it
+        let mut claimed = [false; SCREEN_WIDTH];
+
+        for &i in &sprites[..count] {
+            let y = self.oam[i * 4] as i16 - 16;
+            let x = self.oam[i * 4 + 1] as i16 - 8;
+            let attr = self.oam[i * 4 + 3];
+            let tile = if height == 16 {
+                self.oam[i * 4 + 2] & 0xFE
+            } else {
+                self.oam[i * 4 + 2]
+            };
+
+            let mut row = (ly - y) as u8;
+            if attr & 0x40 != 0 {
+                row = height as u8 - 1 - row;
+            }
+            let bank = if self.cgb {
+                ((attr >> 3) & 0x01) as usize
+            } else {
+                0
+            };
+            let addr = tile as usize * 16 + row as usize * 2;
+            let (lo, hi) = (self.vram[bank][addr], self.vram[bank][addr + 1]);
+
+            for px in 0..8 {
+                let sx = x + px;
+                if sx < 0 || sx >= SCREEN_WIDTH as i16 || claimed[sx as usize] {
+                    continue;
+                }
+                let bit = if attr & 0x20 != 0 { px } else { 7 - px };
+                let color = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                if color == 0 {
+                    continue;
+                }
+
+                let sx = sx as usize;
+                claimed[sx] = true;
+                let bg_wins = attr & 0x80 != 0 || bg_priority[sx];
+                if master_priority && bg_wins && bg_color[sx] != 0 {
+                    continue;
+                }
+
+                line[sx] = if self.cgb {
+                    self.obj_palette.color(attr & 0x07, color)
+                } else if attr & 0x10 != 0 {
+                    dmg_color(self.obp1, color)
+                } else {
+                    dmg_color(self.obp0, color)
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A CGB PPU with the LCD off, so VRAM and OAM take writes. Tile 1 has its left pixel at
+    /// color 1 on the top row and at color 2 on the bottom one in bank 0, and is solid color 3
+    /// in bank 1. It's the first tile of the BG map, with `attr` as its attributes.
+    fn cgb(attr: u8) -> Ppu {
+        let mut ppu = Ppu::new(true);
+        ppu.write(0xff40, 0x11);
+        ppu.write(0x8010, 0x80);
+        ppu.write(0x801F, 0x80);
+        ppu.write(0x9800, 0x01);
+        ppu.write(0xff4f, 1);
+        for addr in 0x8010..0x8020 {
+            ppu.write(addr, 0xFF);
+        }
+        ppu.write(0x9800, attr);
+        ppu.write(0xff4f, 0);
+
+        // Every BG color gets its own value, palette * 4 + color + 1
+        ppu.write(0xff68, 0x80);
+        for i in 0..32u16 {
+            let [lo, hi] = (i + 1).to_le_bytes();
+            ppu.write(0xff69, lo);
+            ppu.write(0xff69, hi);
+        }
+        ppu
+    }
+
+    fn bg(palette: u16, color: u16) -> u16 {
+        palette * 4 + color + 1
+    }
+
+    /// Turns the LCD on with `lcdc` and returns the first eight pixels of line 0.
+    fn first_tile(ppu: &mut Ppu, lcdc: u8) -> [u16; 8] {
+        ppu.write(0xff40, lcdc);
+        ppu.tick(OAM_SCAN_DOTS + DRAWING_DOTS);
+        ppu.frame[..8].try_into().unwrap()
+    }
+
+    #[test]
+    fn vbk_switches_the_bank_the_cpu_sees() {
+        let mut ppu = cgb(0x00);
+        assert_eq!(ppu.read(0xff4f), 0xFE);
+        assert_eq!(ppu.read(0x8010), 0x80);
+        ppu.write(0xff4f, 0xFF);
+        assert_eq!(ppu.read(0xff4f), 0xFF);
+        assert_eq!(ppu.read(0x8010), 0xFF);
+
+        // DMG has no VBK, bank 0 is all there is
+        let mut ppu = Ppu::new(false);
+        ppu.write(0x8000, 0x12);
+        ppu.write(0xff4f, 1);
+        assert_eq!(ppu.read(0xff4f), 0xFF);
+        assert_eq!(ppu.read(0x8000), 0x12);
+    }
+
+    #[test]
+    fn attributes_pick_bank_flip_and_palette() {
+        let mut plain = [bg(0, 0); 8];
+        plain[0] = bg(0, 1);
+        assert_eq!(first_tile(&mut cgb(0x00), 0x91), plain);
+
+        let mut x_flipped = plain;
+        x_flipped.reverse();
+        assert_eq!(first_tile(&mut cgb(0x20), 0x91), x_flipped);
+
+        let y_flipped = first_tile(&mut cgb(0x40), 0x91);
+        assert_eq!(y_flipped[0], bg(0, 2));
+
+        assert_eq!(first_tile(&mut cgb(0x08), 0x91), [bg(0, 3); 8]);
+
+        let palette_3 = first_tile(&mut cgb(0x03), 0x91);
+        assert_eq!(palette_3[..2], [bg(3, 1), bg(3, 0)]);
+    }
+
+    #[test]
+    fn bg_priority_draws_over_sprites() {
+        const SPRITE: u16 = 0x7C00;
+        let with_sprite = |attr| {
+            let mut ppu = cgb(attr);
+            // Tile 2, solid color 1, at the top left
+            ppu.write(0x8020, 0xFF);
+            ppu.write(0xfe00, 16);
+            ppu.write(0xfe01, 8);
+            ppu.write(0xfe02, 2);
+            ppu.write(0xff6a, 0x82);
+            ppu.write(0xff6b, SPRITE as u8);
+            ppu.write(0xff6b, (SPRITE >> 8) as u8);
+            ppu
+        };
+
+        assert_eq!(first_tile(&mut with_sprite(0x00), 0x93), [SPRITE; 8]);
+
+        // Only where the background isn't color 0
+        let mut expected = [SPRITE; 8];
+        expected[0] = bg(0, 1);
+        assert_eq!(first_tile(&mut with_sprite(0x80), 0x93), expected);
+
+        // LCDC bit 0 takes the priority away on CGB
+        assert_eq!(first_tile(&mut with_sprite(0x80), 0x92), [SPRITE; 8]);
+    }
+}
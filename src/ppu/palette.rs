@@ -0,0 +1,105 @@
+/// Size of a CGB palette RAM in bytes: 8 palettes of 4 colors, 2 bytes per color.
+pub const PALETTE_RAM_SIZE: usize = 64;
+
+/// Colors a DMG game sees through BGP/OBP0/OBP1, expressed as BGR555 so both models share one
+/// framebuffer format.
+pub const DMG_SHADES: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+/// One of the two CGB palette memories, addressed through an index register (BCPS/OCPS) and a
+/// data register (BCPD/OCPD).
+///
+/// Bits 0-5 of the index register select a byte in the 64-byte palette RAM, bit 7 asks the
+/// hardware to bump the index after every write to the data register. Colors are stored little
+/// endian as BGR555: `0bbbbbgg gggrrrrr`.
+pub struct CgbPalette {
+    index: u8,
+    auto_increment: bool,
+    ram: [u8; PALETTE_RAM_SIZE],
+}
+
+impl CgbPalette {
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            auto_increment: false,
+            // The boot ROM leaves every color white
+            ram: [0xFF; PALETTE_RAM_SIZE],
+        }
+    }
+
+    /// Reads BCPS/OCPS, bit 6 isn't wired and always reads back set.
+    #[inline]
+    pub fn read_spec(&self) -> u8 {
+        let inc = if self.auto_increment { 0x80 } else { 0x00 };
+        inc | 0x40 | self.index
+    }
+
+    #[inline]
+    pub fn write_spec(&mut self, val: u8) {
+        self.index = val & 0x3F;
+        self.auto_increment = val & 0x80 != 0;
+    }
+
+    /// Reads BCPD/OCPD. The PPU owns palette memory during mode 3, so the CPU gets 0xFF.
+    #[inline]
+    pub fn read_data(&self, accessible: bool) -> u8 {
+        if accessible {
+            self.ram[self.index as usize]
+        } else {
+            0xFF
+        }
+    }
+
+    /// Writes BCPD/OCPD. A blocked write is dropped, but the index still moves on if
+    /// auto-increment is set.
+    #[inline]
+    pub fn write_data(&mut self, val: u8, accessible: bool) {
+        if accessible {
+            self.ram[self.index as usize] = val;
+        }
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Returns color `color` (0-3) of palette `palette` (0-7) as BGR555.
+    #[inline]
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let i = ((palette & 0x07) as usize * 8) + ((color & 0x03) as usize * 2);
+        u16::from_le_bytes([self.ram[i], self.ram[i + 1]]) & 0x7FFF
+    }
+}
+
+/// Maps a DMG palette register (BGP/OBP0/OBP1) and a 2-bit color number onto a shade.
+#[inline]
+pub fn dmg_color(reg: u8, color: u8) -> u16 {
+    DMG_SHADES[((reg >> ((color & 0x03) * 2)) & 0x03) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_increment_wraps() {
+        let mut pal = CgbPalette::new();
+        pal.write_spec(0x80 | 0x3F);
+        pal.write_data(0x12, true);
+        assert_eq!(pal.read_spec(), 0xC0);
+        pal.write_data(0x34, true);
+        assert_eq!(pal.read_spec(), 0xC1);
+        assert_eq!(pal.color(7, 3), 0x12FF);
+        assert_eq!(pal.color(0, 0), 0x7F34);
+    }
+
+    #[test]
+    fn mode_3_blocks_data_but_not_index() {
+        let mut pal = CgbPalette::new();
+        pal.write_spec(0x80);
+        pal.write_data(0x00, false);
+        assert_eq!(pal.read_spec(), 0xC1);
+        assert_eq!(pal.read_data(false), 0xFF);
+        pal.write_spec(0x00);
+        assert_eq!(pal.read_data(true), 0xFF);
+    }
+}
@@ -0,0 +1,45 @@
+// Just enough BMP to get a printout off the device
+
+/// BITMAPFILEHEADER, BITMAPINFOHEADER and a four colour palette.
+pub const HEADER_SIZE: usize = 14 + 40 + 4 * 4;
+
+/// Bytes per row of a 4 bits per pixel image, padded out to 32 bits like BMP wants.
+#[inline]
+pub const fn row_size(width: usize) -> usize {
+    (width.div_ceil(2) + 3) & !3
+}
+
+/// Size of the whole file.
+#[inline]
+pub const fn file_size(width: usize, height: usize) -> usize {
+    HEADER_SIZE + row_size(width) * height
+}
+
+/// Header for a 4 bits per pixel, bottom-up image with `palette` as BGR colours. Rows go after
+/// it, last row first.
+pub fn header(width: usize, height: usize, palette: [[u8; 3]; 4]) -> [u8; HEADER_SIZE] {
+    let mut h = [0u8; HEADER_SIZE];
+    let put16 = |h: &mut [u8], at: usize, v: u16| h[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    let put32 = |h: &mut [u8], at: usize, v: u32| h[at..at + 4].copy_from_slice(&v.to_le_bytes());
+
+    h[0..2].copy_from_slice(b"BM");
+    put32(&mut h, 2, file_size(width, height) as u32);
+    put32(&mut h, 10, HEADER_SIZE as u32);
+
+    put32(&mut h, 14, 40);
+    put32(&mut h, 18, width as u32);
+    put32(&mut h, 22, height as u32);
+    put16(&mut h, 26, 1);
+    put16(&mut h, 28, 4);
+    put32(&mut h, 34, (row_size(width) * height) as u32);
+    // 72 DPI, near enough to the printer's
+    put32(&mut h, 38, 2835);
+    put32(&mut h, 42, 2835);
+    put32(&mut h, 46, 4);
+    put32(&mut h, 50, 4);
+
+    for (i, [b, g, r]) in palette.iter().enumerate() {
+        h[54 + i * 4..54 + i * 4 + 3].copy_from_slice(&[*b, *g, *r]);
+    }
+    h
+}
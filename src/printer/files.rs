@@ -0,0 +1,125 @@
+// Printouts saved as numbered BMP files, host only
+#![cfg(any(test, feature = "std"))]
+
+extern crate std;
+
+use std::format;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use super::PrintSink;
+
+/// Saves each printout into a directory as `print-0001.bmp`, `print-0002.bmp` and so on,
+/// carrying on after whatever's already there.
+pub struct PrintFiles {
+    dir: PathBuf,
+    next: u32,
+    current: Vec<u8>,
+    saved: Vec<PathBuf>,
+    error: Option<io::Error>,
+}
+
+impl PrintFiles {
+    pub fn new(dir: &Path) -> Self {
+        let mut prints = Self {
+            dir: dir.into(),
+            next: 1,
+            current: Vec::new(),
+            saved: Vec::new(),
+            error: None,
+        };
+        while prints.path(prints.next).exists() {
+            prints.next += 1;
+        }
+        prints
+    }
+
+    fn path(&self, number: u32) -> PathBuf {
+        self.dir.join(format!("print-{:04}.bmp", number))
+    }
+
+    /// Every file written so far, oldest first.
+    pub fn saved(&self) -> &[PathBuf] {
+        &self.saved
+    }
+
+    /// Why the last printout that didn't make it to disk failed, if one didn't.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+impl PrintSink for PrintFiles {
+    fn begin(&mut self, len: usize) -> bool {
+        self.current = Vec::with_capacity(len);
+        true
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.current.extend_from_slice(bytes);
+    }
+
+    fn end(&mut self) {
+        let path = self.path(self.next);
+        match std::fs::write(&path, &self.current) {
+            Ok(()) => {
+                self.saved.push(path);
+                self.next += 1;
+            }
+            Err(error) => self.error = Some(error),
+        }
+        self.current.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bmp;
+    use super::*;
+
+    fn print(prints: &mut PrintFiles, shade: u8) -> Vec<u8> {
+        let mut file = Vec::from(bmp::header(8, 2, [[0; 3]; 4]));
+        file.resize(bmp::file_size(8, 2), shade);
+        assert!(prints.begin(file.len()));
+        for chunk in file.chunks(7) {
+            prints.write(chunk);
+        }
+        prints.end();
+        file
+    }
+
+    #[test]
+    fn numbers_each_printout() {
+        let dir = std::env::temp_dir().join(format!("gbc-m4-prints-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut prints = PrintFiles::new(&dir);
+        let first = print(&mut prints, 0x11);
+        let second = print(&mut prints, 0x22);
+        assert_eq!(
+            prints.saved(),
+            [dir.join("print-0001.bmp"), dir.join("print-0002.bmp")]
+        );
+        assert_eq!(std::fs::read(&prints.saved()[0]).unwrap(), first);
+        assert_eq!(std::fs::read(&prints.saved()[1]).unwrap(), second);
+
+        // Another session doesn't overwrite them
+        let mut prints = PrintFiles::new(&dir);
+        print(&mut prints, 0x33);
+        assert_eq!(prints.saved(), [dir.join("print-0003.bmp")]);
+        assert!(prints.take_error().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_error() {
+        let dir = std::env::temp_dir().join(format!("gbc-m4-no-such-dir-{}", std::process::id()));
+        let mut prints = PrintFiles::new(&dir);
+        print(&mut prints, 0);
+        assert!(prints.saved().is_empty());
+        assert!(prints.take_error().is_some());
+    }
+}
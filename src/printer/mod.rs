@@ -0,0 +1,473 @@
+// Game Boy Printer, plugged into the link port
+
+pub mod bmp;
+pub mod files;
+pub mod slots;
+
+use crate::serial::LinkTransport;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+/// What the printer answers the first byte after the checksum with
+const DEVICE_ID: u8 = 0x81;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+pub const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+pub const STATUS_PRINTING: u8 = 0b0000_0010;
+pub const STATUS_FULL: u8 = 0b0000_0100;
+pub const STATUS_UNPROCESSED: u8 = 0b0000_1000;
+pub const STATUS_PACKET_ERROR: u8 = 0b0001_0000;
+
+/// Tiles across a printout
+const TILES_WIDE: usize = 20;
+pub const PRINT_WIDTH: usize = TILES_WIDE * 8;
+/// One DATA packet's worth, two rows of tiles
+const BAND_SIZE: usize = TILES_WIDE * 16 * 2;
+/// The printer's RAM holds nine bands, a whole screen's worth
+const BUFFER_SIZE: usize = BAND_SIZE * 9;
+/// Pixel rows fed per unit of margin
+const MARGIN_ROWS: usize = 8;
+/// Status packets the printer stays busy for after printing, games wait for this to clear
+const BUSY_POLLS: u8 = 4;
+
+/// Shades from white to black, BGR for the BMP palette.
+const SHADES: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+/// Wherever printouts end up: files on host, flash on the board. Each one arrives as a complete
+/// BMP, streamed in order.
+pub trait PrintSink {
+    /// A printout of `len` bytes is on its way. Returning false drops it.
+    fn begin(&mut self, len: usize) -> bool;
+    fn write(&mut self, bytes: &[u8]);
+    fn end(&mut self);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// Speaks the printer's packet protocol from the slave end of the link.
+///
+/// ```text
+/// 0x88 0x33 command compression length(le16) data... checksum(le16) 0x00 0x00
+/// ```
+///
+/// The checksum is the 16-bit sum of everything from the command up to the end of the data.
+/// The printer answers 0x00 to all of it, except the last two bytes, where it sends 0x81 and
+/// then its status.
+pub struct Printer<P: PrintSink> {
+    sink: P,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    received: usize,
+    sum: u16,
+    checksum: u16,
+    packet: [u8; BAND_SIZE],
+    /// Decompressed tile data waiting to be printed
+    buffer: [u8; BUFFER_SIZE],
+    filled: usize,
+    status: u8,
+    /// Status packets left before the current print is done
+    busy: u8,
+}
+
+impl<P: PrintSink> Printer<P> {
+    pub fn new(sink: P) -> Self {
+        Self {
+            sink,
+            state: State::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            received: 0,
+            sum: 0,
+            checksum: 0,
+            packet: [0; BAND_SIZE],
+            buffer: [0; BUFFER_SIZE],
+            filled: 0,
+            status: 0,
+            busy: 0,
+        }
+    }
+
+    pub fn sink(&mut self) -> &mut P {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> P {
+        self.sink
+    }
+
+    #[inline]
+    fn add(&mut self, byte: u8) {
+        self.sum = self.sum.wrapping_add(byte as u16);
+    }
+
+    /// Feeds one byte of a packet through the state machine, returning the printer's side of
+    /// the exchange.
+    fn receive(&mut self, byte: u8) -> u8 {
+        match self.state {
+            State::Magic(i) => {
+                self.state = if byte != MAGIC[i] {
+                    State::Magic(0)
+                } else if i + 1 == MAGIC.len() {
+                    State::Command
+                } else {
+                    State::Magic(i + 1)
+                };
+            }
+            State::Command => {
+                self.command = byte;
+                self.sum = 0;
+                self.add(byte);
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.add(byte);
+                self.state = State::LengthLow;
+            }
+            State::LengthLow => {
+                self.length = byte as usize;
+                self.add(byte);
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.received = 0;
+                self.add(byte);
+                self.state = if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                };
+            }
+            State::Data => {
+                if self.received < BAND_SIZE {
+                    self.packet[self.received] = byte;
+                }
+                self.received += 1;
+                self.add(byte);
+                if self.received == self.length {
+                    self.state = State::ChecksumLow;
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum = byte as u16;
+                self.state = State::ChecksumHigh;
+            }
+            State::ChecksumHigh => {
+                self.checksum |= (byte as u16) << 8;
+                self.state = State::Alive;
+            }
+            State::Alive => {
+                self.state = State::Status;
+                return DEVICE_ID;
+            }
+            State::Status => {
+                self.state = State::Magic(0);
+                self.process();
+                return self.status;
+            }
+        }
+        0x00
+    }
+
+    /// Runs the packet that just finished, ahead of reporting status for it.
+    fn process(&mut self) {
+        if self.checksum != self.sum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        if self.length > BAND_SIZE {
+            self.status |= STATUS_PACKET_ERROR;
+            return;
+        }
+
+        match self.command {
+            CMD_INIT => {
+                self.filled = 0;
+                self.busy = 0;
+                self.status = 0;
+            }
+            CMD_DATA => {
+                // An empty DATA packet just marks the end of the image
+                if self.length > 0 {
+                    self.store();
+                    self.status |= STATUS_UNPROCESSED;
+                }
+            }
+            CMD_PRINT if self.length == 4 => {
+                let [sheets, margins, palette, _exposure] = [
+                    self.packet[0],
+                    self.packet[1],
+                    self.packet[2],
+                    self.packet[3],
+                ];
+                // No sheets means just feed the paper
+                if sheets > 0 {
+                    self.print(margins, palette);
+                }
+                self.filled = 0;
+                self.status &= !(STATUS_UNPROCESSED | STATUS_FULL);
+                self.status |= STATUS_PRINTING;
+                self.busy = BUSY_POLLS;
+            }
+            CMD_STATUS => {
+                if self.busy > 0 {
+                    self.busy -= 1;
+                    if self.busy == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+            }
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    /// Appends the packet's tile data to the buffer, undoing the RLE if it's compressed.
+    ///
+    /// Compressed data is a run of chunks. A control byte with bit 7 clear is followed by that
+    /// many plus one literal bytes, one with bit 7 set is followed by a single byte repeated its
+    /// lower seven bits plus two times.
+    fn store(&mut self) {
+        let data = &self.packet[..self.length];
+        let mut out = self.filled;
+        let mut push = |buffer: &mut [u8; BUFFER_SIZE], byte: u8| {
+            if out < BUFFER_SIZE {
+                buffer[out] = byte;
+                out += 1;
+            }
+        };
+
+        if !self.compressed {
+            for byte in data {
+                push(&mut self.buffer, *byte);
+            }
+        } else {
+            let mut i = 0;
+            while i < data.len() {
+                let control = data[i];
+                i += 1;
+                if control & 0x80 != 0 {
+                    let byte = data.get(i).copied().unwrap_or(0);
+                    i += 1;
+                    for _ in 0..(control & 0x7F) as usize + 2 {
+                        push(&mut self.buffer, byte);
+                    }
+                } else {
+                    let end = (i + control as usize + 1).min(data.len());
+                    for byte in &data[i..end] {
+                        push(&mut self.buffer, *byte);
+                    }
+                    i = end;
+                }
+            }
+        }
+
+        self.filled = out;
+        if self.filled == BUFFER_SIZE {
+            self.status |= STATUS_FULL;
+        }
+    }
+
+    /// Shade index of a pixel in the buffer, before the palette.
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        let tile = (y / 8) * TILES_WIDE + x / 8;
+        let at = tile * 16 + (y % 8) * 2;
+        let bit = 7 - (x % 8);
+        let lo = (self.buffer[at] >> bit) & 1;
+        let hi = (self.buffer[at + 1] >> bit) & 1;
+        (hi << 1) | lo
+    }
+
+    /// Sends whatever's in the buffer to the sink as a BMP, with `margins` worth of blank paper
+    /// above (upper nibble) and below (lower nibble).
+    fn print(&mut self, margins: u8, palette: u8) {
+        // Whole rows of tiles only, a short packet doesn't print half a tile
+        let image = (self.filled / (TILES_WIDE * 16)) * 8;
+        let top = (margins >> 4) as usize * MARGIN_ROWS;
+        let bottom = (margins & 0x0F) as usize * MARGIN_ROWS;
+        let height = top + image + bottom;
+        if image == 0 || !self.sink.begin(bmp::file_size(PRINT_WIDTH, height)) {
+            return;
+        }
+
+        // Games that don't care about the palette send 0, which prints like the usual 0xE4
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        self.sink.write(&bmp::header(PRINT_WIDTH, height, SHADES));
+        let mut row = [0u8; bmp::row_size(PRINT_WIDTH)];
+        for y in (0..height).rev() {
+            row.fill(0);
+            if y >= top && y < top + image {
+                for x in 0..PRINT_WIDTH {
+                    let shade = (palette >> (self.pixel(x, y - top) * 2)) & 0x03;
+                    row[x / 2] |= shade << if x % 2 == 0 { 4 } else { 0 };
+                }
+            }
+            self.sink.write(&row);
+        }
+        self.sink.end();
+    }
+}
+
+impl<P: PrintSink> LinkTransport for Printer<P> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.receive(byte)
+    }
+
+    /// The printer never drives the clock.
+    fn poll_slave(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Prints {
+        done: Vec<Vec<u8>>,
+        current: Vec<u8>,
+    }
+
+    impl PrintSink for Prints {
+        fn begin(&mut self, _len: usize) -> bool {
+            self.current.clear();
+            true
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.current.extend_from_slice(bytes);
+        }
+
+        fn end(&mut self) {
+            self.done.push(core::mem::take(&mut self.current));
+        }
+    }
+
+    /// Sends a whole packet and returns the last two bytes the printer answered with.
+    fn send(printer: &mut Printer<Prints>, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = Vec::from(MAGIC);
+        let header = [
+            command,
+            compressed as u8,
+            data.len() as u8,
+            (data.len() >> 8) as u8,
+        ];
+        packet.extend_from_slice(&header);
+        packet.extend_from_slice(data);
+        let sum = header
+            .iter()
+            .chain(data)
+            .fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        packet.extend_from_slice(&sum.to_le_bytes());
+        packet.extend_from_slice(&[0, 0]);
+
+        let replies: Vec<u8> = packet.iter().map(|b| printer.exchange(*b)).collect();
+        assert!(replies[..replies.len() - 2].iter().all(|r| *r == 0));
+        (replies[replies.len() - 2], replies[replies.len() - 1])
+    }
+
+    #[test]
+    fn prints_a_band_as_bmp() {
+        let mut printer = Printer::new(Prints::default());
+        assert_eq!(send(&mut printer, CMD_INIT, false, &[]), (DEVICE_ID, 0));
+
+        // Top left tile solid colour 3, everything else colour 0
+        let mut band = [0u8; BAND_SIZE];
+        band[..16].fill(0xFF);
+        assert_eq!(
+            send(&mut printer, CMD_DATA, false, &band),
+            (DEVICE_ID, STATUS_UNPROCESSED)
+        );
+        send(&mut printer, CMD_DATA, false, &[]);
+
+        // One unit of margin below, default palette
+        let (_, status) = send(&mut printer, CMD_PRINT, false, &[1, 0x01, 0x00, 0x40]);
+        assert_eq!(status, STATUS_PRINTING);
+
+        let prints = &printer.sink().done;
+        assert_eq!(prints.len(), 1);
+        let bmp = &prints[0];
+        let height = 16 + MARGIN_ROWS;
+        assert_eq!(bmp.len(), bmp::file_size(PRINT_WIDTH, height));
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp[22], height as u8);
+
+        // Bottom-up, so the top left pixel is in the last row
+        let row = |y: usize| {
+            let at = bmp::HEADER_SIZE + (height - 1 - y) * bmp::row_size(PRINT_WIDTH);
+            &bmp[at..at + bmp::row_size(PRINT_WIDTH)]
+        };
+        assert_eq!(row(0)[..4], [0x33, 0x33, 0x33, 0x33]);
+        assert_eq!(row(0)[4], 0x00);
+        assert_eq!(row(8)[0], 0x00);
+        assert!(row(height - 1).iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn busy_clears_after_a_few_polls() {
+        let mut printer = Printer::new(Prints::default());
+        send(&mut printer, CMD_INIT, false, &[]);
+        send(&mut printer, CMD_DATA, false, &[0; BAND_SIZE]);
+        send(&mut printer, CMD_PRINT, false, &[1, 0, 0xE4, 0x40]);
+
+        let mut polls = 0;
+        while send(&mut printer, CMD_STATUS, false, &[]).1 & STATUS_PRINTING != 0 {
+            polls += 1;
+        }
+        assert_eq!(polls, BUSY_POLLS - 1);
+    }
+
+    #[test]
+    fn rle_expands_runs_and_literals() {
+        let mut printer = Printer::new(Prints::default());
+        // 0x81: 3 x 0xAA, 0x01: two literals
+        send(
+            &mut printer,
+            CMD_DATA,
+            true,
+            &[0x81, 0xAA, 0x01, 0x12, 0x34],
+        );
+        assert_eq!(printer.filled, 5);
+        assert_eq!(printer.buffer[..5], [0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported_and_dropped() {
+        let mut printer = Printer::new(Prints::default());
+        let packet = [0x88, 0x33, CMD_DATA, 0, 1, 0, 0x55, 0xFF, 0xFF, 0, 0];
+        let replies: Vec<u8> = packet.iter().map(|b| printer.exchange(*b)).collect();
+        assert_eq!(replies[10], STATUS_CHECKSUM_ERROR);
+        assert_eq!(printer.filled, 0);
+    }
+}
@@ -0,0 +1,58 @@
+// Stored printouts take turns in a ring of slots, this works out whose turn it is
+
+/// Where a stored printout's sequence number goes: the BMP header's two reserved fields, which
+/// nothing reading the file looks at.
+pub const SEQUENCE_OFFSET: usize = 6;
+
+/// The sequence number `header` was stamped with, `None` if it isn't a printout at all.
+pub fn sequence(header: &[u8]) -> Option<u32> {
+    if header.len() < SEQUENCE_OFFSET + 4 || header[..2] != *b"BM" {
+        return None;
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&header[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 4]);
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// The slot and sequence number for the next printout, given what each slot was stamped with.
+/// It goes in after the newest, so once they're all taken it's always the oldest that makes way.
+pub fn next_slot(sequences: &[Option<u32>]) -> (usize, u32) {
+    let newest = sequences
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, sequence)| sequence.map(|sequence| (slot, sequence)))
+        .max_by_key(|(_, sequence)| *sequence);
+    match newest {
+        Some((slot, sequence)) => ((slot + 1) % sequences.len(), sequence.wrapping_add(1)),
+        None => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_up_in_order() {
+        assert_eq!(next_slot(&[None; 4]), (0, 0));
+        assert_eq!(next_slot(&[Some(0), Some(1), None, None]), (2, 2));
+    }
+
+    #[test]
+    fn wraps_around_to_the_oldest() {
+        assert_eq!(next_slot(&[Some(0), Some(1), Some(2), Some(3)]), (0, 4));
+        assert_eq!(next_slot(&[Some(4), Some(5), Some(2), Some(3)]), (2, 6));
+        assert_eq!(next_slot(&[Some(8), Some(5), Some(6), Some(7)]), (1, 9));
+        // A slot that got erased but never finished doesn't throw the order off
+        assert_eq!(next_slot(&[Some(4), None, Some(2), Some(3)]), (1, 5));
+    }
+
+    #[test]
+    fn reads_the_stamp_back() {
+        let mut header = super::super::bmp::header(160, 16, [[0; 3]; 4]);
+        assert_eq!(sequence(&header), Some(0));
+        header[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 4].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(sequence(&header), Some(7));
+        assert_eq!(sequence(&[0xFF; 16]), None);
+    }
+}
@@ -0,0 +1,341 @@
+use super::LinkTransport;
+
+pub const MAX_PLAYERS: usize = 4;
+
+/// What a Game Boy answers the ping header and the first status byte with
+const ACK: u8 = 0x88;
+/// What player 1 answers the ping header with to start the game
+const START: u8 = 0xAA;
+const PING_HEADER: u8 = 0xFE;
+/// Sent to everyone between the ping and transmission phases
+const SYNC: u8 = 0xCC;
+const SYNC_BYTES: u8 = 4;
+const PING_BYTES: usize = 4;
+
+/// Largest packet a player can ask for, bigger sizes get clamped
+const MAX_PACKET: usize = 16;
+
+/// T-cycles to shift one byte out at 8192 Hz
+const BYTE_CYCLES: u32 = 8 * 512;
+/// Extra T-cycles between bytes per unit of the rate players ask for
+const GAP_CYCLES: u32 = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Phase {
+    /// Polling for who's there, `byte` into the four byte ping packet
+    Ping { byte: usize },
+    /// Player 1 asked to start, sending the sync bytes
+    Sync { left: u8 },
+    /// `byte` into a round of `4 * size` bytes
+    Transmit { byte: usize },
+}
+
+/// The DMG-07 four player adapter. It drives the clock for every Game Boy plugged into it, so
+/// each of them sits listening on an external clock while the adapter `exchange`s bytes with
+/// them through its ports.
+///
+/// In the ping phase the adapter keeps sending `0xFE` followed by three copies of a status byte,
+/// with the player's number in bits 0-2 and a bit per connected player from bit 4 up. Players
+/// answer with `0x88 0x88 rate size`. Once player 1 answers the header with `0xAA` instead,
+/// the adapter sends four `0xCC` and switches to transmission with player 1's rate and size.
+///
+/// From then on every round is `4 * size` bytes long. Players send their packet in the first
+/// `size` bytes, while the adapter sends everyone what all four sent in the previous round,
+/// player 1 first. A round where player 1 sends nothing but `0xFF` goes back to pinging.
+///
+/// Ports are any `LinkTransport`, one end of a `Wire` for instances in the same process or a
+/// `FramedLink` to another board.
+pub struct FourPlayerAdapter {
+    phase: Phase,
+    /// Bit per player that acknowledged the last ping
+    connected: u8,
+    /// What each player answered this ping packet with
+    replies: [[u8; PING_BYTES]; MAX_PLAYERS],
+    rate: u8,
+    size: usize,
+    /// Packets coming in this round
+    incoming: [[u8; MAX_PACKET]; MAX_PLAYERS],
+    /// Last round's packets, going out to everyone this round
+    outgoing: [u8; MAX_PACKET * MAX_PLAYERS],
+    /// T-cycles until the next byte gets clocked
+    countdown: u32,
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::Ping { byte: 0 },
+            connected: 0,
+            replies: [[0; PING_BYTES]; MAX_PLAYERS],
+            rate: 0,
+            size: 1,
+            incoming: [[0xFF; MAX_PACKET]; MAX_PLAYERS],
+            outgoing: [0xFF; MAX_PACKET * MAX_PLAYERS],
+            countdown: BYTE_CYCLES,
+        }
+    }
+
+    /// Bit per connected player, player 1 in bit 0.
+    #[inline]
+    pub fn connected(&self) -> u8 {
+        self.connected
+    }
+
+    #[inline]
+    pub fn transmitting(&self) -> bool {
+        matches!(self.phase, Phase::Transmit { .. })
+    }
+
+    #[inline]
+    fn period(&self) -> u32 {
+        BYTE_CYCLES + (self.rate & 0x0F) as u32 * GAP_CYCLES
+    }
+
+    /// Advances the adapter's clock by `cycles` T-cycles, swapping bytes with `ports` whenever
+    /// one is due. `ports[0]` is player 1, and there can be fewer than four.
+    pub fn tick(&mut self, cycles: u32, ports: &mut [&mut dyn LinkTransport]) {
+        let mut cycles = cycles;
+        while cycles >= self.countdown {
+            cycles -= self.countdown;
+            self.clock(ports);
+            self.countdown = self.period();
+        }
+        self.countdown -= cycles;
+    }
+
+    /// Clocks one byte out to every port.
+    fn clock(&mut self, ports: &mut [&mut dyn LinkTransport]) {
+        match self.phase {
+            Phase::Ping { byte } => {
+                for (player, port) in ports.iter_mut().enumerate().take(MAX_PLAYERS) {
+                    let out = if byte == 0 {
+                        PING_HEADER
+                    } else {
+                        (self.connected << 4) | (player as u8 + 1)
+                    };
+                    self.replies[player][byte] = port.exchange(out);
+                }
+                self.phase = if byte + 1 < PING_BYTES {
+                    Phase::Ping { byte: byte + 1 }
+                } else {
+                    self.pinged(ports.len())
+                };
+            }
+            Phase::Sync { left } => {
+                for port in ports.iter_mut() {
+                    port.exchange(SYNC);
+                }
+                self.phase = if left > 1 {
+                    Phase::Sync { left: left - 1 }
+                } else {
+                    self.incoming = [[0xFF; MAX_PACKET]; MAX_PLAYERS];
+                    self.outgoing = [0xFF; MAX_PACKET * MAX_PLAYERS];
+                    Phase::Transmit { byte: 0 }
+                };
+            }
+            Phase::Transmit { byte } => {
+                for (player, port) in ports.iter_mut().enumerate().take(MAX_PLAYERS) {
+                    let reply = port.exchange(self.outgoing[byte]);
+                    if byte < self.size && self.connected & (1 << player) != 0 {
+                        self.incoming[player][byte] = reply;
+                    }
+                }
+                self.phase = if byte + 1 < self.size * MAX_PLAYERS {
+                    Phase::Transmit { byte: byte + 1 }
+                } else {
+                    self.round()
+                };
+            }
+        }
+    }
+
+    /// Works out who's there at the end of a ping packet, and whether to start.
+    fn pinged(&mut self, players: usize) -> Phase {
+        self.connected = 0;
+        for (player, [header, status, _, _]) in self.replies.iter().enumerate().take(players) {
+            let acked = *header == ACK || (player == 0 && *header == START);
+            if acked && *status == ACK {
+                self.connected |= 1 << player;
+            }
+        }
+
+        let [header, _, rate, size] = self.replies[0];
+        if header == START && self.connected & 1 != 0 {
+            self.rate = rate;
+            self.size = (size as usize).clamp(1, MAX_PACKET);
+            Phase::Sync { left: SYNC_BYTES }
+        } else {
+            Phase::Ping { byte: 0 }
+        }
+    }
+
+    /// Hands this round's packets over to go out next round.
+    fn round(&mut self) -> Phase {
+        if self.incoming[0][..self.size].iter().all(|b| *b == 0xFF) {
+            self.connected = 0;
+            self.rate = 0;
+            return Phase::Ping { byte: 0 };
+        }
+
+        for (player, packet) in self.incoming.iter_mut().enumerate() {
+            let at = player * self.size;
+            self.outgoing[at..at + self.size].copy_from_slice(&packet[..self.size]);
+            packet.fill(0xFF);
+        }
+        Phase::Transmit { byte: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::super::*;
+    use super::*;
+
+    const SIZE: u8 = 2;
+    const ROUND: usize = SIZE as usize * MAX_PLAYERS;
+
+    /// Plays along with the adapter the way a game would, loading SB with the answer to the next
+    /// byte as soon as the last one arrives.
+    struct Player {
+        serial: Serial,
+        number: u8,
+        /// Whether player 1 should start the game at the next ping
+        start: bool,
+        /// Bytes received since the last ping header, or into the current round
+        position: usize,
+        syncs: u8,
+        transmitting: bool,
+        received: [u8; ROUND],
+        rounds: usize,
+    }
+
+    impl Player {
+        fn new(number: u8) -> Self {
+            let mut serial = Serial::new(false);
+            serial.write(0xff01, ACK);
+            serial.write(0xff02, 0x80);
+            Self {
+                serial,
+                number,
+                start: false,
+                position: 0,
+                syncs: 0,
+                transmitting: false,
+                received: [0; ROUND],
+                rounds: 0,
+            }
+        }
+
+        fn packet_byte(&self, i: usize) -> u8 {
+            if i < SIZE as usize {
+                self.number << 4 | i as u8
+            } else {
+                0
+            }
+        }
+
+        fn service(&mut self, link: &mut LoopbackLink<'_>) {
+            if self.serial.service(link) == 0 {
+                return;
+            }
+            let byte = self.serial.read(0xff01);
+
+            let next = if self.transmitting {
+                self.received[self.position] = byte;
+                self.position = (self.position + 1) % ROUND;
+                if self.position == 0 {
+                    self.rounds += 1;
+                }
+                self.packet_byte(self.position)
+            } else if byte == SYNC {
+                self.syncs += 1;
+                if self.syncs == SYNC_BYTES {
+                    self.transmitting = true;
+                    self.position = 0;
+                }
+                self.packet_byte(0)
+            } else {
+                if byte == PING_HEADER {
+                    self.position = 0;
+                }
+                self.position += 1;
+                match self.position {
+                    1 => ACK,
+                    2 => 0,
+                    3 => SIZE,
+                    _ if self.start => START,
+                    _ => ACK,
+                }
+            };
+
+            self.serial.write(0xff01, next);
+            self.serial.write(0xff02, 0x80);
+        }
+    }
+
+    fn run(adapter: &mut FourPlayerAdapter, players: &mut [Player], wires: &[Wire], bytes: usize) {
+        let mut ends: Vec<_> = wires.iter().map(|w| w.ends()).collect();
+        for _ in 0..bytes {
+            for (player, (_, end)) in players.iter_mut().zip(ends.iter_mut()) {
+                player.service(end);
+            }
+            let mut ports: Vec<&mut dyn LinkTransport> = ends
+                .iter_mut()
+                .map(|(port, _)| port as &mut dyn LinkTransport)
+                .collect();
+            adapter.tick(BYTE_CYCLES, &mut ports);
+            for (player, (_, end)) in players.iter_mut().zip(ends.iter_mut()) {
+                player.service(end);
+            }
+        }
+    }
+
+    #[test]
+    fn four_players_ping_then_trade_packets() {
+        let wires = [Wire::new(), Wire::new(), Wire::new(), Wire::new()];
+        let mut players = [
+            Player::new(1),
+            Player::new(2),
+            Player::new(3),
+            Player::new(4),
+        ];
+        let mut adapter = FourPlayerAdapter::new();
+
+        run(&mut adapter, &mut players, &wires, PING_BYTES * 2);
+        assert_eq!(adapter.connected(), 0b1111);
+        assert!(!adapter.transmitting());
+
+        // SB already holds the answer to the next header, so it takes one more ping to start
+        players[0].start = true;
+        run(
+            &mut adapter,
+            &mut players,
+            &wires,
+            PING_BYTES * 2 + SYNC_BYTES as usize,
+        );
+        assert!(adapter.transmitting());
+
+        run(&mut adapter, &mut players, &wires, ROUND * 3);
+        for player in &players {
+            assert!(player.rounds >= 2);
+            assert_eq!(
+                player.received,
+                [0x10, 0x11, 0x20, 0x21, 0x30, 0x31, 0x40, 0x41]
+            );
+        }
+    }
+
+    #[test]
+    fn missing_players_arent_connected() {
+        let wires = [Wire::new(), Wire::new()];
+        let mut players = [Player::new(1), Player::new(2)];
+        let mut adapter = FourPlayerAdapter::new();
+
+        run(&mut adapter, &mut players, &wires, PING_BYTES * 2);
+        assert_eq!(adapter.connected(), 0b0011);
+    }
+}
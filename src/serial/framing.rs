@@ -0,0 +1,305 @@
+use super::{ByteStream, LinkTransport};
+
+/// Master's byte, clocked into whoever's listening on the other end
+const TAG_DATA: u8 = 0xA5;
+/// The other end is listening for an external clock with this byte in SB
+const TAG_READY: u8 = 0x5A;
+/// The other end stopped listening, forget the byte it advertised
+const TAG_IDLE: u8 = 0xC3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Frame {
+    Data(u8),
+    Ready(u8),
+    Idle,
+}
+
+impl Frame {
+    fn encode(self) -> [u8; 2] {
+        match self {
+            Frame::Data(byte) => [TAG_DATA, byte],
+            Frame::Ready(byte) => [TAG_READY, byte],
+            Frame::Idle => [TAG_IDLE, 0],
+        }
+    }
+}
+
+/// Pieces frames back together a byte at a time. Anything that isn't a tag where one is
+/// expected gets dropped, which is how we fall back into step after line noise or a reset on
+/// the other end.
+struct Decoder {
+    tag: Option<u8>,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Self { tag: None }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Frame> {
+        match self.tag.take() {
+            Some(TAG_DATA) => Some(Frame::Data(byte)),
+            Some(TAG_READY) => Some(Frame::Ready(byte)),
+            Some(_) => Some(Frame::Idle),
+            None => {
+                if matches!(byte, TAG_DATA | TAG_READY | TAG_IDLE) {
+                    self.tag = Some(byte);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// A link over a `ByteStream` with enough framing to get the clock right across a slow pipe.
+///
+/// A real cable swaps both bytes in the same eight clocks, but over a UART the master can't wait
+/// that long for an answer. So the listening end advertises its SB up front with a READY frame,
+/// and the master only ever trades against an advertised byte. The slave then stays stalled in
+/// `poll_slave` until the master's DATA frame shows up, however long the trip takes.
+///
+/// If nobody has advertised by the time the master gives up, it reads 0xFF like with no cable,
+/// and nothing goes out on the wire.
+pub struct FramedLink<S: ByteStream> {
+    stream: S,
+    decoder: Decoder,
+    /// What we last sent in a READY frame
+    advertised: Option<u8>,
+    /// What the other end last sent in a READY frame
+    peer_ready: Option<u8>,
+    /// A DATA frame that hasn't been picked up by `poll_slave` yet
+    delivered: Option<u8>,
+    /// Reads the master attempts before giving up and taking 0xFF
+    timeout: u32,
+}
+
+impl<S: ByteStream> FramedLink<S> {
+    pub fn new(stream: S, timeout: u32) -> Self {
+        Self {
+            stream,
+            decoder: Decoder::new(),
+            advertised: None,
+            peer_ready: None,
+            delivered: None,
+            timeout,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn send(&mut self, frame: Frame) {
+        for byte in frame.encode() {
+            self.stream.write_byte(byte);
+        }
+    }
+
+    /// Reads whatever's arrived. Returns false once the stream runs dry.
+    fn pump(&mut self) -> bool {
+        let byte = match self.stream.read_byte() {
+            Some(byte) => byte,
+            None => return false,
+        };
+        match self.decoder.push(byte) {
+            Some(Frame::Data(byte)) => self.delivered = Some(byte),
+            Some(Frame::Ready(byte)) => self.peer_ready = Some(byte),
+            Some(Frame::Idle) => self.peer_ready = None,
+            None => {}
+        }
+        true
+    }
+
+    fn drain(&mut self) {
+        while self.pump() {}
+    }
+}
+
+impl<S: ByteStream> LinkTransport for FramedLink<S> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        // We're driving the clock now, so whatever we advertised is stale
+        if self.advertised.take().is_some() {
+            self.send(Frame::Idle);
+        }
+        self.delivered = None;
+
+        self.drain();
+        for _ in 0..self.timeout {
+            if let Some(reply) = self.peer_ready.take() {
+                self.send(Frame::Data(byte));
+                return reply;
+            }
+            self.pump();
+        }
+        0xFF
+    }
+
+    fn poll_slave(&mut self, byte: u8) -> Option<u8> {
+        self.drain();
+        if let Some(received) = self.delivered.take() {
+            // The next transfer has to be advertised again, even if SB doesn't change
+            self.advertised = None;
+            return Some(received);
+        }
+        if self.advertised != Some(byte) {
+            self.advertised = Some(byte);
+            self.send(Frame::Ready(byte));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::super::*;
+    use super::*;
+
+    /// One direction of an in-memory pipe.
+    struct Queue {
+        bytes: [u8; 64],
+        head: usize,
+        len: usize,
+    }
+
+    impl Queue {
+        const fn new() -> Self {
+            Self {
+                bytes: [0; 64],
+                head: 0,
+                len: 0,
+            }
+        }
+    }
+
+    struct Pipe {
+        queues: [RefCell<Queue>; 2],
+    }
+
+    impl Pipe {
+        const fn new() -> Self {
+            Self {
+                queues: [RefCell::new(Queue::new()), RefCell::new(Queue::new())],
+            }
+        }
+
+        fn ends(&self) -> (PipeEnd<'_>, PipeEnd<'_>) {
+            (
+                PipeEnd {
+                    pipe: self,
+                    side: 0,
+                },
+                PipeEnd {
+                    pipe: self,
+                    side: 1,
+                },
+            )
+        }
+    }
+
+    struct PipeEnd<'p> {
+        pipe: &'p Pipe,
+        side: usize,
+    }
+
+    impl<'p> ByteStream for PipeEnd<'p> {
+        fn write_byte(&mut self, byte: u8) {
+            let mut q = self.pipe.queues[1 - self.side].borrow_mut();
+            let tail = (q.head + q.len) % q.bytes.len();
+            q.bytes[tail] = byte;
+            q.len += 1;
+        }
+
+        fn read_byte(&mut self) -> Option<u8> {
+            let mut q = self.pipe.queues[self.side].borrow_mut();
+            if q.len == 0 {
+                return None;
+            }
+            let byte = q.bytes[q.head];
+            q.head = (q.head + 1) % q.bytes.len();
+            q.len -= 1;
+            Some(byte)
+        }
+    }
+
+    #[test]
+    fn decoder_skips_garbage() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0x00), None);
+        assert_eq!(decoder.push(0x12), None);
+        assert_eq!(decoder.push(TAG_READY), None);
+        assert_eq!(decoder.push(TAG_DATA), Some(Frame::Ready(TAG_DATA)));
+        assert_eq!(decoder.push(TAG_IDLE), None);
+        assert_eq!(decoder.push(0x00), Some(Frame::Idle));
+    }
+
+    #[test]
+    fn slave_stalls_until_master_clocks() {
+        let pipe = Pipe::new();
+        let (a_end, b_end) = pipe.ends();
+        let mut a_link = FramedLink::new(a_end, 16);
+        let mut b_link = FramedLink::new(b_end, 16);
+        let mut a = Serial::new(false);
+        let mut b = Serial::new(false);
+
+        b.write(0xff01, 0x34);
+        b.write(0xff02, 0x80);
+        assert_eq!(b.service(&mut b_link), 0);
+        assert_eq!(b.service(&mut b_link), 0);
+
+        a.write(0xff01, 0x12);
+        a.write(0xff02, 0x81);
+        a.tick(4096);
+        assert_eq!(a.service(&mut a_link), SERIAL_INTERRUPT);
+        assert_eq!(a.read(0xff01), 0x34);
+
+        assert_eq!(b.service(&mut b_link), SERIAL_INTERRUPT);
+        assert_eq!(b.read(0xff01), 0x12);
+    }
+
+    #[test]
+    fn master_without_listener_reads_ones() {
+        let pipe = Pipe::new();
+        let (a_end, b_end) = pipe.ends();
+        let mut a_link = FramedLink::new(a_end, 16);
+        let mut b_link = FramedLink::new(b_end, 16);
+
+        assert_eq!(a_link.exchange(0x12), 0xFF);
+        // Nothing went out, so a late listener isn't handed a stale byte
+        assert_eq!(b_link.poll_slave(0x34), None);
+        assert_eq!(b_link.poll_slave(0x34), None);
+    }
+
+    #[test]
+    fn same_byte_is_advertised_again_after_a_transfer() {
+        let pipe = Pipe::new();
+        let (a_end, b_end) = pipe.ends();
+        let mut a_link = FramedLink::new(a_end, 16);
+        let mut b_link = FramedLink::new(b_end, 16);
+
+        for i in 0..3 {
+            assert_eq!(b_link.poll_slave(0x77), None);
+            assert_eq!(a_link.exchange(i), 0x77);
+            assert_eq!(b_link.poll_slave(0x77), Some(i));
+        }
+    }
+
+    #[test]
+    fn becoming_master_withdraws_advert() {
+        let pipe = Pipe::new();
+        let (a_end, b_end) = pipe.ends();
+        let mut a_link = FramedLink::new(a_end, 16);
+        let mut b_link = FramedLink::new(b_end, 16);
+
+        // Both sides listen, like Pokémon does before picking who clocks
+        assert_eq!(a_link.poll_slave(0x01), None);
+        assert_eq!(b_link.poll_slave(0x02), None);
+
+        assert_eq!(a_link.exchange(0x10), 0x02);
+        assert_eq!(b_link.poll_slave(0x02), Some(0x10));
+
+        // a isn't listening anymore, so b clocking on its own gets nothing
+        assert_eq!(b_link.exchange(0x20), 0xFF);
+    }
+}
@@ -0,0 +1,207 @@
+// Serial port and whatever's plugged into it
+
+mod four_player;
+mod framing;
+mod loopback;
+pub mod pty;
+mod stream;
+
+pub use four_player::*;
+pub use framing::*;
+pub use loopback::*;
+pub use stream::*;
+
+/// Interrupt request bit as it sits in IF
+pub const SERIAL_INTERRUPT: u8 = 0b0000_1000;
+
+const SC_START: u8 = 0b1000_0000;
+const SC_FAST: u8 = 0b0000_0010;
+const SC_INTERNAL: u8 = 0b0000_0001;
+
+/// T-cycles per bit with the 8192 Hz internal clock. The clock is derived from the same
+/// counter as DIV, so this holds in CPU cycles at either speed.
+const NORMAL_BIT_CYCLES: u32 = 512;
+/// T-cycles per bit with the 262144 Hz CGB clock.
+const FAST_BIT_CYCLES: u32 = 16;
+
+/// The other end of the link cable, moving a whole byte at a time.
+pub trait LinkTransport {
+    /// We're the clock master and just shifted out `byte`. Returns what the other end shifted
+    /// back, which is 0xFF if nobody's there.
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// We're waiting on an external clock with `byte` in SB. Returns the incoming byte once the
+    /// other end has clocked a transfer, at which point it got `byte` in return.
+    fn poll_slave(&mut self, byte: u8) -> Option<u8>;
+}
+
+/// Nothing plugged in. The master reads all ones and an externally clocked transfer never
+/// finishes.
+pub struct NoCable;
+
+impl LinkTransport for NoCable {
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        0xFF
+    }
+
+    fn poll_slave(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// SB (0xFF01) and SC (0xFF02).
+///
+/// Internally clocked transfers take eight bit times, after which the byte is swapped with the
+/// transport in `service`. Externally clocked ones sit there until the transport says the
+/// other end clocked them.
+pub struct Serial {
+    cgb: bool,
+    sb: u8,
+    sc: u8,
+    /// T-cycles left on an internally clocked transfer
+    countdown: u32,
+    /// An internally clocked transfer is done shifting and wants the other end's byte
+    ready: bool,
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            cgb,
+            sb: 0,
+            sc: 0,
+            countdown: 0,
+            ready: false,
+        }
+    }
+
+    #[inline]
+    fn transferring(&self) -> bool {
+        self.sc & SC_START != 0
+    }
+
+    #[inline]
+    fn internal(&self) -> bool {
+        self.sc & SC_INTERNAL != 0
+    }
+
+    #[inline]
+    fn bit_cycles(&self) -> u32 {
+        if self.cgb && self.sc & SC_FAST != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        }
+    }
+
+    /// Advances an internally clocked transfer by `cycles` T-cycles.
+    #[inline]
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.transferring() || !self.internal() || self.ready {
+            return;
+        }
+        self.countdown = self.countdown.saturating_sub(cycles);
+        if self.countdown == 0 {
+            self.ready = true;
+        }
+    }
+
+    /// Swaps bytes with `link` if a transfer is due, returning the IF bits that raises.
+    pub fn service<T: LinkTransport + ?Sized>(&mut self, link: &mut T) -> u8 {
+        if !self.transferring() {
+            return 0;
+        }
+
+        let received = if self.internal() {
+            if !self.ready {
+                return 0;
+            }
+            link.exchange(self.sb)
+        } else {
+            match link.poll_slave(self.sb) {
+                Some(byte) => byte,
+                None => return 0,
+            }
+        };
+
+        self.sb = received;
+        self.sc &= !SC_START;
+        self.ready = false;
+        SERIAL_INTERRUPT
+    }
+
+    /// Whether SC asks for an externally clocked transfer, for transports that need to know
+    /// which end is which.
+    #[inline]
+    pub fn listening(&self) -> bool {
+        self.transferring() && !self.internal()
+    }
+
+    #[inline]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff01 => self.sb,
+            0xff02 if self.cgb => 0x7C | self.sc,
+            0xff02 => 0x7E | self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    #[inline]
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff01 => self.sb = val,
+            0xff02 => {
+                let mask = if self.cgb {
+                    SC_START | SC_FAST | SC_INTERNAL
+                } else {
+                    SC_START | SC_INTERNAL
+                };
+                self.sc = val & mask;
+                self.ready = false;
+                if self.transferring() && self.internal() {
+                    self.countdown = 8 * self.bit_cycles();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_transfer_takes_eight_bits() {
+        let mut serial = Serial::new(false);
+        serial.write(0xff01, 0x42);
+        serial.write(0xff02, 0x81);
+
+        serial.tick(8 * NORMAL_BIT_CYCLES - 4);
+        assert_eq!(serial.service(&mut NoCable), 0);
+        assert_eq!(serial.read(0xff02), 0xFF);
+
+        serial.tick(4);
+        assert_eq!(serial.service(&mut NoCable), SERIAL_INTERRUPT);
+        assert_eq!(serial.read(0xff01), 0xFF);
+        assert_eq!(serial.read(0xff02), 0x7F);
+    }
+
+    #[test]
+    fn cgb_fast_clock() {
+        let mut serial = Serial::new(true);
+        serial.write(0xff02, 0x83);
+        serial.tick(8 * FAST_BIT_CYCLES);
+        assert_eq!(serial.service(&mut NoCable), SERIAL_INTERRUPT);
+    }
+
+    #[test]
+    fn external_clock_waits_forever_without_cable() {
+        let mut serial = Serial::new(false);
+        serial.write(0xff02, 0x80);
+        serial.tick(100_000);
+        assert_eq!(serial.service(&mut NoCable), 0);
+        assert!(serial.listening());
+    }
+}
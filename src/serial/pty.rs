@@ -0,0 +1,161 @@
+// Terminal byte stream: a pseudo-terminal for tests, or a real serial port such as the board's
+// USB CDC one, so a host-side instance can be the other end of the link
+#![cfg(all(any(test, feature = "std"), target_os = "linux"))]
+
+extern crate std;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+use super::ByteStream;
+
+/// Turns an error return from libc into the `io::Error` it set.
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// A terminal in raw mode: one side of a pseudo-terminal, or a serial port. A USB CDC port looks
+/// the same either way from the PC.
+pub struct PtyStream(File);
+
+impl PtyStream {
+    /// Opens a new pseudo-terminal and returns its master and slave sides.
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let master = unsafe {
+            let fd = check(libc::posix_openpt(
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK,
+            ))?;
+            let master = File::from_raw_fd(fd);
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+            master
+        };
+
+        let mut name = [0 as libc::c_char; 64];
+        let err = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        let path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        let path = path
+            .to_str()
+            .map_err(|_| io::Error::from(ErrorKind::InvalidData))?;
+
+        Ok((Self(master), Self::open(Path::new(path))?))
+    }
+
+    /// Opens the terminal at `path`, e.g. `/dev/ttyACM0` with the board plugged in.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+
+        // No echo, no line buffering, no CR/LF games
+        unsafe {
+            let mut termios: libc::termios = core::mem::zeroed();
+            check(libc::tcgetattr(file.as_raw_fd(), &mut termios))?;
+            libc::cfmakeraw(&mut termios);
+            check(libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios))?;
+        }
+
+        Ok(Self(file))
+    }
+}
+
+impl ByteStream for PtyStream {
+    fn write_byte(&mut self, byte: u8) {
+        loop {
+            match self.0.write(&[byte]) {
+                Ok(1) => return,
+                Err(e) if e.kind() != ErrorKind::WouldBlock => panic!("pty write: {}", e),
+                _ => std::thread::yield_now(),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        match self.0.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+
+    use super::super::*;
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::harness::program;
+
+    /// The tty layer hands bytes across asynchronously, so give it some time.
+    const TIMEOUT: u32 = 1_000_000;
+
+    /// Puts `sb` in SB, starts a transfer with SC set to `sc` and waits in a loop.
+    fn linked(sb: u8, sc: u8) -> Box<CPU> {
+        let mut cpu = Box::new(CPU::new(true));
+        // LD A,sb / LDH (SB),A / LD A,sc / LDH (SC),A / loop: JR loop
+        cpu.load_rom(program(&[
+            0x3E, sb, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE,
+        ]));
+        cpu
+    }
+
+    /// Steps both in lockstep until neither has a transfer going, and returns how many T-cycles
+    /// that took.
+    fn transfer(
+        a: &mut CPU,
+        b: &mut CPU,
+        a_link: &mut impl LinkTransport,
+        b_link: &mut impl LinkTransport,
+    ) -> u32 {
+        let mut cycles = 0;
+        for _ in 0..TIMEOUT {
+            cycles += a.execute().unwrap();
+            a.service_link(a_link);
+            b.execute().unwrap();
+            b.service_link(b_link);
+            if cycles > 64 && a.peek(0xff02) & 0x80 == 0 && b.peek(0xff02) & 0x80 == 0 {
+                return cycles;
+            }
+        }
+        panic!("the transfer never finished");
+    }
+
+    #[test]
+    fn instances_link_over_a_pty() {
+        let (a_end, b_end) = PtyStream::pair().unwrap();
+        let mut a_link = FramedLink::new(a_end, TIMEOUT);
+        let mut b_link = FramedLink::new(b_end, TIMEOUT);
+
+        // A clocks the transfer, B listens
+        let mut a = linked(0x12, 0x81);
+        let mut b = linked(0x34, 0x80);
+        let cycles = transfer(&mut a, &mut b, &mut a_link, &mut b_link);
+        assert_eq!(a.peek(0xff01), 0x34);
+        assert_eq!(b.peek(0xff01), 0x12);
+        assert_ne!(a.peek(0xff0f) & SERIAL_INTERRUPT, 0);
+        assert_ne!(b.peek(0xff0f) & SERIAL_INTERRUPT, 0);
+        // Eight bits at 8192 Hz, give or take the instructions either side
+        assert!((4096..4096 + 64).contains(&cycles), "{}", cycles);
+
+        // And back the other way, with the clock on the other end
+        let mut a = linked(0x56, 0x80);
+        let mut b = linked(0x78, 0x81);
+        transfer(&mut a, &mut b, &mut a_link, &mut b_link);
+        assert_eq!(a.peek(0xff01), 0x78);
+        assert_eq!(b.peek(0xff01), 0x56);
+    }
+}
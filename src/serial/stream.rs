@@ -0,0 +1,47 @@
+use super::LinkTransport;
+
+/// A plain byte pipe: a UART, a socket, a pseudo-terminal...
+pub trait ByteStream {
+    fn write_byte(&mut self, byte: u8);
+    /// Returns the next received byte, without blocking.
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+/// A link over a `ByteStream` with no framing at all. The master writes its byte and spins for
+/// the reply, the slave answers every byte it receives with whatever's in SB.
+///
+/// Only good for two ends that run independently of each other, a master spinning on a slave
+/// stepped from the same thread never gets its reply.
+pub struct StreamLink<S: ByteStream> {
+    stream: S,
+    /// Reads the master attempts before giving up and taking 0xFF
+    timeout: u32,
+}
+
+impl<S: ByteStream> StreamLink<S> {
+    pub fn new(stream: S, timeout: u32) -> Self {
+        Self { stream, timeout }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: ByteStream> LinkTransport for StreamLink<S> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.stream.write_byte(byte);
+        for _ in 0..self.timeout {
+            if let Some(reply) = self.stream.read_byte() {
+                return reply;
+            }
+        }
+        0xFF
+    }
+
+    fn poll_slave(&mut self, byte: u8) -> Option<u8> {
+        let received = self.stream.read_byte()?;
+        self.stream.write_byte(byte);
+        Some(received)
+    }
+}
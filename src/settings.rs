@@ -0,0 +1,106 @@
+// Settings that survive a power cycle
+
+use crate::io::chords::{ChordSettings, CHORD_SETTINGS_SIZE};
+
+const MAGIC: [u8; 2] = *b"GB";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 3;
+/// Bytes a serialized `Settings` takes up, checksum included.
+pub const SETTINGS_SIZE: usize = HEADER_SIZE + CHORD_SETTINGS_SIZE + 1;
+
+/// Wherever settings get persisted to: a block of flash on the board, a file or a buffer on host.
+pub trait SettingsStore {
+    /// Fills `buf` with what was last saved, returns false if there's nothing to read.
+    fn read(&mut self, buf: &mut [u8]) -> bool;
+    /// Replaces whatever was saved with `buf`.
+    fn write(&mut self, buf: &[u8]) -> bool;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Settings {
+    pub chords: ChordSettings,
+}
+
+impl Settings {
+    /// Serializes as magic, version, the payload and an additive checksum over all of it.
+    pub fn to_bytes(self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0u8; SETTINGS_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC);
+        bytes[2] = VERSION;
+        self.chords
+            .encode(&mut bytes[HEADER_SIZE..HEADER_SIZE + CHORD_SETTINGS_SIZE]);
+        bytes[SETTINGS_SIZE - 1] = checksum(&bytes[..SETTINGS_SIZE - 1]);
+        bytes
+    }
+
+    /// Returns `None` for blank, corrupt or outdated settings.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < SETTINGS_SIZE
+            || bytes[..2] != MAGIC
+            || bytes[2] != VERSION
+            || bytes[SETTINGS_SIZE - 1] != checksum(&bytes[..SETTINGS_SIZE - 1])
+        {
+            return None;
+        }
+
+        Some(Self {
+            chords: ChordSettings::decode(&bytes[HEADER_SIZE..HEADER_SIZE + CHORD_SETTINGS_SIZE]),
+        })
+    }
+
+    /// Loads settings from `store`, falling back on the defaults.
+    pub fn load<S: SettingsStore>(store: &mut S) -> Self {
+        let mut bytes = [0u8; SETTINGS_SIZE];
+        if store.read(&mut bytes) {
+            Self::from_bytes(&bytes).unwrap_or_default()
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn save<S: SettingsStore>(&self, store: &mut S) -> bool {
+        store.write(&self.to_bytes())
+    }
+}
+
+#[inline]
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ram([u8; SETTINGS_SIZE]);
+
+    impl SettingsStore for Ram {
+        fn read(&mut self, buf: &mut [u8]) -> bool {
+            buf.copy_from_slice(&self.0);
+            true
+        }
+
+        fn write(&mut self, buf: &[u8]) -> bool {
+            self.0.copy_from_slice(buf);
+            true
+        }
+    }
+
+    #[test]
+    fn blank_flash_loads_defaults() {
+        let mut store = Ram([0xFF; SETTINGS_SIZE]);
+        assert_eq!(Settings::load(&mut store), Settings::default());
+    }
+
+    #[test]
+    fn saved_settings_load_back() {
+        let mut store = Ram([0xFF; SETTINGS_SIZE]);
+        let mut settings = Settings::default();
+        settings.chords.turbo_a = true;
+        assert!(settings.save(&mut store));
+        assert_eq!(Settings::load(&mut store), settings);
+
+        store.0[4] ^= 0x01;
+        assert_eq!(Settings::load(&mut store), Settings::default());
+    }
+}
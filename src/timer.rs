@@ -0,0 +1,178 @@
+// DIV, TIMA, TMA and TAC, all hanging off one 16-bit counter
+
+/// Interrupt request bit as it sits in IF
+pub const TIMER_INTERRUPT: u8 = 0b0000_0100;
+
+/// Bit of the internal counter each TAC clock select watches. TIMA ticks when that bit falls.
+const TAC_TAPS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+const TAC_ENABLE: u8 = 0b0000_0100;
+
+pub struct Timer {
+    /// Internal counter bumped every T-cycle, DIV is its upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed last M-cycle and reads 0 until it's reloaded this one
+    overflow: bool,
+    /// TIMA was reloaded from TMA during the current M-cycle, writes to it are dropped
+    reloading: bool,
+    /// T-cycles that haven't added up to a full M-cycle yet
+    remainder: u32,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
+            remainder: 0,
+        }
+    }
+
+    /// The AND of the enable bit and the tapped counter bit. Anything that drops it from high to
+    /// low counts as a tick, which is where the DIV and TAC write glitches come from.
+    #[inline]
+    fn signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & TAC_TAPS[(self.tac & 0x03) as usize] != 0
+    }
+
+    #[inline]
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflow = true;
+        }
+    }
+
+    /// Advances the timer by `cycles` T-cycles and returns the IF bits it wants raised.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut ints = 0;
+        self.remainder += cycles;
+
+        while self.remainder >= 4 {
+            self.remainder -= 4;
+            self.reloading = false;
+
+            if self.overflow {
+                self.overflow = false;
+                self.reloading = true;
+                self.tima = self.tma;
+                ints |= TIMER_INTERRUPT;
+            }
+
+            let before = self.signal();
+            self.counter = self.counter.wrapping_add(4);
+            if before && !self.signal() {
+                self.increment();
+            }
+        }
+
+        ints
+    }
+
+    #[inline]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            0xff07 => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    #[inline]
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff04 => {
+                let before = self.signal();
+                self.counter = 0;
+                if before {
+                    self.increment();
+                }
+            }
+            // Writing during the reload cycle is ignored, the reload wins
+            0xff05 if !self.reloading => {
+                // Writing during the overflow cycle cancels the reload and the interrupt
+                self.tima = val;
+                self.overflow = false;
+            }
+            0xff06 => {
+                self.tma = val;
+                if self.reloading {
+                    self.tima = val;
+                }
+            }
+            0xff07 => {
+                let before = self.signal();
+                self.tac = val & 0x07;
+                if before && !self.signal() {
+                    self.increment();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xff07, tac);
+        timer
+    }
+
+    #[test]
+    fn tima_counts_at_selected_rate() {
+        let mut timer = timer(0x05);
+        timer.tick(16 * 10);
+        assert_eq!(timer.read(0xff05), 10);
+        assert_eq!(timer.read(0xff04), 0);
+        timer.tick(256 - 160);
+        assert_eq!(timer.read(0xff04), 1);
+    }
+
+    #[test]
+    fn div_write_on_high_bit_ticks_tima() {
+        let mut timer = timer(0x05);
+        timer.tick(8);
+        timer.write(0xff04, 0x12);
+        assert_eq!(timer.read(0xff05), 1);
+        assert_eq!(timer.read(0xff04), 0);
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_late() {
+        let mut timer = timer(0x05);
+        timer.write(0xff06, 0xAB);
+        timer.write(0xff05, 0xFF);
+
+        assert_eq!(timer.tick(16), 0);
+        assert_eq!(timer.read(0xff05), 0x00);
+        assert_eq!(timer.tick(4), TIMER_INTERRUPT);
+        assert_eq!(timer.read(0xff05), 0xAB);
+
+        // Writes during the reload cycle are ignored
+        timer.write(0xff05, 0x10);
+        assert_eq!(timer.read(0xff05), 0xAB);
+    }
+
+    #[test]
+    fn tima_write_cancels_pending_reload() {
+        let mut timer = timer(0x05);
+        timer.write(0xff05, 0xFF);
+        timer.tick(16);
+        timer.write(0xff05, 0x10);
+        assert_eq!(timer.tick(4), 0);
+        assert_eq!(timer.read(0xff05), 0x10);
+    }
+}